@@ -1,5 +1,6 @@
 % vim: filetype=prolog
-:- module(basic). 
+% Generated drawing rules are called qualified, e.g. `basic:box(L, Attrs)`
+:- module(basic, []).
 term_expansion(drawing_object(Name), PrologClauses) :-
     findall(Rule, generate_drawing_rule(Name, Rule), Rules), 
     maplist(expand_term, Rules, PrologClauses)
@@ -1,5 +1,5 @@
 % vim: filetype=prolog
-:- module(containers).
+:- module(containers, [around//4]).
 offset_sign(nw, "-", "").
 offset_sign(se, "", "-").
 offset_sign(sw, "-", "-").
@@ -1,6 +1,6 @@
 % vim: filetype=prolog
 
-:- module(file_as_lines).
+:- module(file_as_lines, [show//1]).
 
 file_text(N,'') --> file_text(N, ' ').
 file_text(N, T) --> 
@@ -1,6 +1,6 @@
 % vim: filetype=prolog
 
-:- module(grid).
+:- module(grid, [grid2x2//4]).
 grid2x2(A,B,C,D) -->
   label("GRID",
     (box(A), "down", box(C), "right", box(D), "up", box(B))).
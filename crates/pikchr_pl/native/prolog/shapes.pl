@@ -1,5 +1,5 @@
 % vim: filetype=prolog
-:- module(shapes).
+:- module(shapes, [box//1, entry//2, exit//2, move_to//2, pipe//4, socket//3]).
 
 socket(L, Dir, T) --> "box at ", label(L), ".", as(Dir),
                       " width 20%", " fit", " fill white", semicolon,
@@ -1,5 +1,5 @@
 % vim: filetype=prolog
-:- module(sized_box).
+:- module(sized_box, [sized_box//3, sized_box//4]).
 
 sized_box(L, W,H) --> sized_box(L,W,H,[]). 
 sized_box(L, W,H,Attrs) -->
@@ -1,5 +1,5 @@
 % vim: filetype=prolog
-:- module(testing).
+:- module(testing, [tests/0]).
 :- dynamic(test/1).
 test(label_atom) :- phrase(label(a), "A"). 
 test(label_string) :- phrase(label("abc"), "ABC").
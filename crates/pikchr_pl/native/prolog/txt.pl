@@ -1,6 +1,6 @@
 % vim: filetype=prolog
 
-:- module(txt).
+:- module(txt, [text_above//2, text_center//2, text_inner//2, text_inside//2, text_outer//2]).
 text_above(L, T) --> "move to ", L, ".nw;", "text ", quoted(T), " center above ;". 
 text_inside(L, T) --> "text ", quoted(T), "center with .center at ", L, ".center".
 
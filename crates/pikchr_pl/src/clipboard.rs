@@ -0,0 +1,58 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{borrow::Cow, fmt::Display};
+
+use crate::export::{self, ExportError};
+
+/// Copies are pasted mostly into chats and slides, 2x keeps them crisp on
+/// HiDPI screens.
+const IMAGE_SCALE: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    Image,
+    Svg,
+    Pikchr,
+}
+
+impl CopyFormat {
+    pub const ALL: [CopyFormat; 3] = [CopyFormat::Image, CopyFormat::Svg, CopyFormat::Pikchr];
+}
+
+impl Display for CopyFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyFormat::Image => write!(f, "Image"),
+            CopyFormat::Svg => write!(f, "SVG Markup"),
+            CopyFormat::Pikchr => write!(f, "Pikchr Source"),
+        }
+    }
+}
+
+/// Puts rendered diagram on the clipboard as an image.
+///
+/// Clipboard is kept by the caller - on X11 contents are served only as long
+/// as it lives.
+pub fn copy_image(clipboard: &mut arboard::Clipboard, svg: &str) -> Result<(), ExportError> {
+    let pixmap = export::rasterize(svg, IMAGE_SCALE)?;
+    // Pixmap is premultiplied, but it's fully opaque so bytes are the same
+    let image = arboard::ImageData {
+        width:  pixmap.width() as usize,
+        height: pixmap.height() as usize,
+        bytes:  Cow::Borrowed(pixmap.data()),
+    };
+    clipboard
+        .set_image(image)
+        .map_err(|e| ExportError::ClipboardFailure(e.to_string()))
+}
@@ -0,0 +1,369 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Completion popup under the cursor. Offers nonterminals of the file and
+//! its enabled modules in Prolog mode, keywords in Pikchr mode.
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use iced::{
+    Element, Length, Task, Theme,
+    keyboard::{Key, key::Named},
+    widget::{
+        button, column, container, operation, pin, row, space, text,
+        text_editor::{Action, Content, Cursor, Edit, KeyPress, Position},
+    },
+};
+
+use crate::{
+    OperatingMode,
+    document::Document,
+    editor_state::Editor,
+    gutter,
+    messages::{CompletionAction, Message},
+    prolog_source::{self, Indicator},
+    text_highlighting::PIKCHR_KEYWORDS,
+};
+
+const TEXT_SIZE: f32 = 12.0;
+const WIDTH: f32 = 300.0;
+const MAX_SHOWN: usize = 8;
+
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub popup: Option<Popup>,
+    /// Counts edits, a pause is stale once more was typed
+    typed:     u64,
+}
+
+impl Completion {
+    /// Closes the popup, a pending pause won't open it again.
+    pub fn close(&mut self) {
+        self.popup = None;
+        self.typed += 1;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Popup {
+    /// Where the completed word starts
+    start:      Position,
+    word:       String,
+    candidates: Vec<Item>,
+    /// Highlighted item, index into matching ones
+    selected:   usize,
+}
+
+impl Popup {
+    fn matching(&self) -> Vec<&Item> {
+        self.candidates
+            .iter()
+            .filter(|item| item.insert.starts_with(&self.word))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    /// Replaces the word before the cursor
+    insert: String,
+    label:  String,
+    /// Where it's defined, or the kind of keyword
+    detail: String,
+}
+
+pub fn handle(editor: &mut Editor, action: CompletionAction) -> Task<Message> {
+    match action {
+        CompletionAction::Requested => {
+            open(editor, false);
+            Task::none()
+        },
+        CompletionAction::Paused(typed) => {
+            if typed == editor.completion.typed && editor.completion.popup.is_none() {
+                open(editor, true);
+            }
+            Task::none()
+        },
+        CompletionAction::Next => {
+            if let Some(popup) = editor.completion.popup.as_mut() {
+                let count = popup.matching().len();
+                popup.selected = (popup.selected + 1).min(count.saturating_sub(1));
+            }
+            Task::none()
+        },
+        CompletionAction::Previous => {
+            if let Some(popup) = editor.completion.popup.as_mut() {
+                popup.selected = popup.selected.saturating_sub(1);
+            }
+            Task::none()
+        },
+        CompletionAction::Accepted => {
+            let selected = editor.completion.popup.as_ref().map_or(0, |popup| popup.selected);
+            accept(editor, selected)
+        },
+        // Clicking took the focus away
+        CompletionAction::Picked(index) => {
+            Task::batch([accept(editor, index), operation::focus("editor")])
+        },
+    }
+}
+
+/// Follows edits: narrows an open popup, or waits for typing to pause
+/// before opening one.
+pub fn edited(editor: &mut Editor, action: &Action) -> Task<Message> {
+    editor.completion.typed += 1;
+    if editor.completion.popup.is_some() {
+        refresh(editor);
+        return Task::none();
+    }
+    let delay = editor.keymap.completion_delay;
+    match action {
+        Action::Edit(Edit::Insert(c)) if delay > 0 && (c.is_alphanumeric() || *c == '_') => {
+            let typed = editor.completion.typed;
+            Task::perform(tokio::time::sleep(Duration::from_millis(delay)), move |_| {
+                Message::Completion(CompletionAction::Paused(typed))
+            })
+        },
+        _ => Task::none(),
+    }
+}
+
+/// Keys the open popup takes from the editor.
+pub fn intercept(keypress: &KeyPress) -> Option<CompletionAction> {
+    if !keypress.modifiers.is_empty() {
+        return None;
+    }
+    match keypress.key.as_ref() {
+        Key::Named(Named::ArrowDown) => Some(CompletionAction::Next),
+        Key::Named(Named::ArrowUp) => Some(CompletionAction::Previous),
+        Key::Named(Named::Enter | Named::Tab) => Some(CompletionAction::Accepted),
+        _ => None,
+    }
+}
+
+/// Opens the popup for the word before the cursor. `automatic` ones need
+/// a word and something to add to it.
+fn open(editor: &mut Editor, automatic: bool) {
+    let document = editor.doc();
+    let Some((start, word)) = word_before_cursor(&document.content, document.operating_mode)
+    else {
+        return;
+    };
+    if automatic && word.is_empty() {
+        return;
+    }
+    let popup = Popup {
+        start,
+        word,
+        candidates: candidates(document),
+        selected: 0,
+    };
+    let matching = popup.matching();
+    let useful = if automatic {
+        matching.iter().any(|item| item.insert != popup.word)
+    } else {
+        !matching.is_empty()
+    };
+    if useful {
+        editor.completion.popup = Some(popup);
+    }
+}
+
+/// Narrows the popup to the word typed so far, closes it once the cursor
+/// leaves the word or nothing matches.
+fn refresh(editor: &mut Editor) {
+    let document = editor.doc();
+    let word = word_before_cursor(&document.content, document.operating_mode);
+    let Some(popup) = editor.completion.popup.as_mut() else {
+        return;
+    };
+    match word {
+        Some((start, word)) if start == popup.start => {
+            popup.word = word;
+            popup.selected = 0;
+            if popup.matching().is_empty() {
+                editor.completion.popup = None;
+            }
+        },
+        _ => editor.completion.popup = None,
+    }
+}
+
+/// Replaces the word with the item at `index` of the matching ones.
+fn accept(editor: &mut Editor, index: usize) -> Task<Message> {
+    let Some(popup) = editor.completion.popup.take() else {
+        return Task::none();
+    };
+    let matching = popup.matching();
+    let Some(item) = matching.get(index.min(matching.len().saturating_sub(1))) else {
+        return Task::none();
+    };
+    let insert = Arc::new(item.insert.clone());
+    let document = editor.doc_mut();
+    document.undo_stack.push(&document.content);
+    document.dirty = true;
+    let position = document.content.cursor().position;
+    document.content.move_to(Cursor {
+        position,
+        selection: Some(popup.start),
+    });
+    document.content.perform(Action::Edit(Edit::Paste(insert)));
+    editor.completion.close();
+    Task::done(Message::RunLogic)
+}
+
+/// Word left of the cursor and where it starts, `None` with a selection.
+/// In Prolog mode `:` is part of it, for module qualified nonterminals.
+fn word_before_cursor(content: &Content, mode: OperatingMode) -> Option<(Position, String)> {
+    let cursor = content.cursor();
+    if cursor.selection.is_some() {
+        return None;
+    }
+    let position = cursor.position;
+    let line = content.line(position.line)?;
+    let before = line.text.get(..position.column)?;
+    let is_word = |c: char| {
+        c.is_alphanumeric() || c == '_' || (c == ':' && mode == OperatingMode::PrologMode)
+    };
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word(c))
+        .last()
+        .map_or(before.len(), |(index, _)| index);
+    let start_position = Position {
+        line:   position.line,
+        column: start,
+    };
+    Some((start_position, before[start..].to_string()))
+}
+
+fn candidates(document: &Document) -> Vec<Item> {
+    let mut items = match document.operating_mode {
+        OperatingMode::PikchrMode => {
+            PIKCHR_KEYWORDS
+                .iter()
+                .map(|(keyword, kind)| Item {
+                    insert: keyword.to_string(),
+                    label:  keyword.to_string(),
+                    detail: kind.to_string(),
+                })
+                .collect()
+        },
+        OperatingMode::PrologMode => nonterminals(document),
+    };
+    // Unqualified names first
+    items.sort_by(|a, b| {
+        (a.insert.contains(':'), &a.insert).cmp(&(b.insert.contains(':'), &b.insert))
+    });
+    items
+}
+
+/// Nonterminals of the file, then those of enabled modules, both exported
+/// and module qualified. The file's own win over module ones.
+fn nonterminals(document: &Document) -> Vec<Item> {
+    let mut seen = BTreeSet::new();
+    let mut items = vec![];
+    let mut add = |module: Option<&str>, indicator: Indicator, detail: &str| {
+        if !indicator.nonterminal {
+            return;
+        }
+        let insert = match module {
+            Some(module) => format!("{}:{}", module, indicator.name),
+            None => indicator.name.clone(),
+        };
+        let label = format!("{}//{}", insert, indicator.arity);
+        if seen.insert(label.clone()) {
+            items.push(Item {
+                insert,
+                label,
+                detail: detail.to_string(),
+            });
+        }
+    };
+
+    for clause in prolog_source::clauses(&document.content.text()) {
+        add(None, clause.indicator, "this file");
+    }
+    let modules = &document.modules;
+    for (name, module) in &modules.available_modules {
+        if !modules.is_enabled(name) {
+            continue;
+        }
+        let defined = module.defined();
+        let Some(declaration) = prolog_source::module_declaration(&module.code) else {
+            // Loaded into `user`, nothing to qualify
+            for indicator in defined {
+                add(None, indicator, name.as_str());
+            }
+            continue;
+        };
+        let exports = declaration.exports.unwrap_or_else(|| defined.clone());
+        for indicator in exports {
+            add(None, indicator, name.as_str());
+        }
+        for indicator in defined {
+            add(Some(declaration.name.as_str()), indicator, name.as_str());
+        }
+    }
+    items
+}
+
+/// Items below the start of the completed word.
+pub fn view<'a>(popup: &'a Popup, content: &Content) -> Element<'a, Message> {
+    let column_chars = content
+        .line(popup.start.line)
+        .and_then(|line| line.text.get(..popup.start.column).map(|text| text.chars().count()))
+        .unwrap_or(0);
+    let position = gutter::below(content.line_count(), popup.start.line, column_chars);
+
+    let matching = popup.matching();
+    let selected = popup.selected.min(matching.len().saturating_sub(1));
+    // Keeps the highlighted item in view
+    let skip = (selected + 1).saturating_sub(MAX_SHOWN);
+    let items = matching
+        .into_iter()
+        .enumerate()
+        .skip(skip)
+        .take(MAX_SHOWN)
+        .map(|(index, item)| {
+            let style: fn(&Theme, button::Status) -> button::Style = if index == selected {
+                button::primary
+            } else {
+                button::text
+            };
+            button(
+                row![
+                    text(&item.label).size(TEXT_SIZE).font(iced::font::Font::MONOSPACE),
+                    space::horizontal(),
+                    text(&item.detail).size(TEXT_SIZE),
+                ]
+                .spacing(10),
+            )
+            .style(style)
+            .padding([2, 5])
+            .width(Length::Fill)
+            .on_press(Message::Completion(CompletionAction::Picked(index)))
+            .into()
+        });
+
+    pin(
+        container(column(items))
+            .style(container::bordered_box)
+            .width(WIDTH)
+            .padding(2),
+    )
+    .x(position.x)
+    .y(position.y)
+    .into()
+}
@@ -0,0 +1,345 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Go to definition and references of the name under the cursor.
+//!
+//! Definitions in modules open in the modules pane, which shows their
+//! source read-only.
+
+use iced::{
+    Element, Font, Length, Task,
+    widget::{button, column, container, operation, row, scrollable, text, text_editor::Content},
+};
+
+use crate::{
+    PaneContent,
+    document::Document,
+    editor_state::Editor,
+    messages::{DefinitionAction, Message},
+    module_panel,
+    prolog_source,
+};
+
+const TEXT_SIZE: f32 = 12.0;
+const MAX_HEIGHT: f32 = 150.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// 0-based line of the active document
+    File(usize),
+    /// Module as listed in the modules pane, and 0-based line
+    Module(String, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Reference {
+    location: Location,
+    /// The line, trimmed
+    text:     String,
+}
+
+/// Bar above the editor with the outcome of a lookup.
+#[derive(Debug, Clone)]
+pub struct References {
+    title: String,
+    found: Vec<Reference>,
+}
+
+pub fn handle(editor: &mut Editor, action: DefinitionAction) -> Task<Message> {
+    match action {
+        DefinitionAction::GoTo => {
+            let document = editor.doc();
+            let Some(word) = word_at(&document.content) else {
+                return Task::none();
+            };
+            match definition(document, &word) {
+                Some(location) => {
+                    editor.references = None;
+                    show(editor, location)
+                },
+                None => {
+                    editor.references = Some(References {
+                        title: format!("No definition of {}", word),
+                        found: vec![],
+                    });
+                    Task::none()
+                },
+            }
+        },
+        DefinitionAction::References => {
+            let document = editor.doc();
+            let Some(word) = word_at(&document.content) else {
+                return Task::none();
+            };
+            // Calls may be qualified or not, either way they count
+            let name = word.rsplit(':').next().unwrap_or(&word);
+            let found = references(document, name);
+            editor.references = Some(References {
+                title: format!("{} references to {}", found.len(), name),
+                found,
+            });
+            Task::none()
+        },
+        DefinitionAction::Show(location) => show(editor, location),
+    }
+}
+
+/// Name under the cursor, module qualified if it is.
+fn word_at(content: &Content) -> Option<String> {
+    let position = content.cursor().position;
+    let line = content.line(position.line)?;
+    let text = &line.text;
+    let column = position.column.min(text.len());
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == ':';
+    let start = text[..column]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word(c))
+        .last()
+        .map_or(column, |(index, _)| index);
+    let end = text[column..]
+        .find(|c: char| !is_word(c))
+        .map_or(text.len(), |index| column + index);
+    // `:-` and the like
+    let word = text[start..end].trim_matches(':');
+    (!word.is_empty()).then(|| word.to_string())
+}
+
+/// First clause of `word` in the file, or in a module: the named one for
+/// qualified words, else any that has it, enabled ones first.
+fn definition(document: &Document, word: &str) -> Option<Location> {
+    let (module, name) = match word.rsplit_once(':') {
+        Some((module, name)) => (Some(module), name),
+        None => (None, word),
+    };
+    if module.is_none()
+        && let Some(line) = first_clause(&document.content.text(), name)
+    {
+        return Some(Location::File(line));
+    }
+    let modules = &document.modules;
+    let mut available: Vec<_> = modules.available_modules.iter().collect();
+    available.sort_by_key(|(key, _)| !modules.is_enabled(key));
+    available.into_iter().find_map(|(key, code)| {
+        if let Some(module) = module {
+            let declared = prolog_source::module_declaration(&code.code);
+            if key != module && declared.is_none_or(|declaration| declaration.name != module) {
+                return None;
+            }
+        }
+        let line = first_clause(&code.code, name)?;
+        Some(Location::Module(key.clone(), line))
+    })
+}
+
+fn first_clause(source: &str, name: &str) -> Option<usize> {
+    prolog_source::clauses(source)
+        .into_iter()
+        .find(|clause| clause.indicator.name == name)
+        .map(|clause| clause.line)
+}
+
+/// Uses of `name` in the file, then in enabled modules.
+fn references(document: &Document, name: &str) -> Vec<Reference> {
+    let found = |source: &str, location: &dyn Fn(usize) -> Location| -> Vec<Reference> {
+        let lines: Vec<&str> = source.lines().collect();
+        prolog_source::references(source, name)
+            .into_iter()
+            .map(|line| Reference {
+                location: location(line),
+                text:     lines[line].trim().to_string(),
+            })
+            .collect()
+    };
+    let mut references = found(&document.content.text(), &Location::File);
+    let modules = &document.modules;
+    for (key, module) in &modules.available_modules {
+        if modules.is_enabled(key) {
+            references.extend(found(&module.code, &|line| Location::Module(key.clone(), line)));
+        }
+    }
+    references
+}
+
+/// Moves the cursor there, or opens the module's source on that line.
+fn show(editor: &mut Editor, location: Location) -> Task<Message> {
+    match location {
+        Location::File(line) => {
+            Task::batch([operation::focus("editor"), editor.jump_to_line(line)])
+        },
+        Location::Module(module, line) => {
+            editor.viewed_module = Some(module);
+            editor.viewed_module_line = Some(line);
+            editor.open_side_pane(PaneContent::Modules);
+            module_panel::scroll_to_line(line)
+        },
+    }
+}
+
+/// Title with the references found, if any.
+pub fn view(references: &References) -> Element<'_, Message> {
+    let entries = references.found.iter().map(|reference| {
+        let place = match &reference.location {
+            Location::File(line) => format!("{}", line + 1),
+            Location::Module(module, line) => format!("{}:{}", module, line + 1),
+        };
+        button(
+            row![
+                text(place).size(TEXT_SIZE).width(Length::Fixed(120.0)),
+                text(&reference.text).size(TEXT_SIZE).font(Font::MONOSPACE),
+            ]
+            .spacing(10),
+        )
+        .style(button::text)
+        .width(Length::Fill)
+        .on_press(Message::Definition(DefinitionAction::Show(reference.location.clone())))
+        .into()
+    });
+    column![
+        text(&references.title).size(TEXT_SIZE),
+        container(scrollable(column(entries))).max_height(MAX_HEIGHT),
+    ]
+    .spacing(5)
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use iced::widget::text_editor::{Cursor, Position};
+
+    use super::*;
+    use crate::{
+        OperatingMode,
+        prolog_modules::{Module, ModuleOrigin, PrologModules},
+    };
+
+    /// Document with `source` and only the given modules, `(key, code,
+    /// enabled)`.
+    fn document(source: &str, modules: &[(&str, &str, bool)]) -> Document {
+        let mut document = Document::new(0, source, OperatingMode::PrologMode);
+        let mut available = PrologModules::with_disabled(Vec::<&str>::new());
+        available.available_modules = modules
+            .iter()
+            .map(|(key, code, _)| {
+                let module = Module {
+                    code:   Arc::from(*code),
+                    origin: ModuleOrigin::User,
+                };
+                (key.to_string(), module)
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (key, _, enabled) in modules {
+            available.set_enabled(key, *enabled);
+        }
+        document.modules = available;
+        document
+    }
+
+    fn word_at_column(line: &str, column: usize) -> Option<String> {
+        let mut content = Content::with_text(line);
+        content.move_to(Cursor {
+            position:  Position { line: 0, column },
+            selection: None,
+        });
+        word_at(&content)
+    }
+
+    #[test]
+    fn word_at_keeps_module_and_trims_operators() {
+        let line = "a :- txt:text_outer, head:-b.";
+        assert_eq!(word_at_column(line, 0).as_deref(), Some("a"));
+        assert_eq!(word_at_column(line, 3), None);
+        assert_eq!(word_at_column(line, 12).as_deref(), Some("txt:text_outer"));
+        assert_eq!(word_at_column(line, 23).as_deref(), Some("head"));
+    }
+
+    #[test]
+    fn qualified_word_resolves_to_named_module() {
+        let document = document(
+            "text_outer --> [].\n",
+            &[
+                ("other", "text_outer --> [other].\n", true),
+                (
+                    "text",
+                    ":- module(txt, [text_outer//0]).\ntext_outer --> [].\n",
+                    true,
+                ),
+            ],
+        );
+        assert_eq!(
+            definition(&document, "txt:text_outer"),
+            Some(Location::Module(String::from("text"), 1))
+        );
+        assert_eq!(
+            definition(&document, "other:text_outer"),
+            Some(Location::Module(String::from("other"), 0))
+        );
+    }
+
+    #[test]
+    fn buffer_definition_wins_over_modules() {
+        let document = document(
+            "x.\ncircle --> [].\n",
+            &[("shapes", "circle --> [c].\n", true)],
+        );
+        assert_eq!(definition(&document, "circle"), Some(Location::File(1)));
+    }
+
+    #[test]
+    fn enabled_modules_are_searched_first() {
+        let document = document(
+            "",
+            &[
+                ("a_shapes", "box --> [a].\n", false),
+                ("b_shapes", "x.\nbox --> [b].\n", true),
+            ],
+        );
+        assert_eq!(
+            definition(&document, "box"),
+            Some(Location::Module(String::from("b_shapes"), 1))
+        );
+        // Disabled ones still have it
+        assert_eq!(
+            definition(&document, "a_shapes:box"),
+            Some(Location::Module(String::from("a_shapes"), 0))
+        );
+        assert_eq!(definition(&document, "circle"), None);
+    }
+
+    #[test]
+    fn references_in_buffer_and_enabled_modules() {
+        let document = document(
+            "diagram --> box.\n",
+            &[
+                ("disabled", "uses --> box.\n", false),
+                ("shapes", "box --> [].\ntwo --> box, shapes:box.\n", true),
+            ],
+        );
+        let found: Vec<(Location, String)> = references(&document, "box")
+            .into_iter()
+            .map(|reference| (reference.location, reference.text))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Location::File(0), String::from("diagram --> box.")),
+                (
+                    Location::Module(String::from("shapes"), 1),
+                    String::from("two --> box, shapes:box.")
+                ),
+            ]
+        );
+    }
+}
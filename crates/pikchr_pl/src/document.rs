@@ -0,0 +1,214 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+
+use iced::{
+    Size,
+    widget::{svg, text_editor::Content},
+};
+use pikchr_pro::{
+    pikchr::{self, PikchrCode},
+    prolog::{self, RenderError, engine::trealla::CancellationToken},
+};
+use tokio::sync::watch;
+
+use crate::{
+    ApplicationError, OperatingMode,
+    editor_state::Buffered,
+    heredoc_parser, preview,
+    prolog_modules::{self, Program, PrologModules},
+    source_map,
+    string_ext::StringExt,
+    undo::UndoStack,
+};
+
+/// Renders finish asynchronously, they find their document by this id as
+/// tabs may have been reordered or closed meanwhile.
+pub type DocumentId = usize;
+
+/// Single open tab.
+pub struct Document {
+    pub id:                   DocumentId,
+    pub content:              Content,
+    pub undo_stack:           UndoStack,
+    pub current_file:         Option<PathBuf>,
+    pub dirty:                bool,
+    /// Tab closes once a save finishes
+    pub close_when_saved:     bool,
+    pub operating_mode:       OperatingMode,
+    pub svg_handle:           Option<svg::Handle>,
+    pub svg_source:           Option<String>,
+    pub svg_size:             Option<Size>,
+    pub preview_view:         preview::View,
+    pub last_successful:      bool,
+    pub last_error:           Buffered<String>,
+    /// Editor line the last error points at
+    pub error_line:           Option<usize>,
+    pub pikchr_code:          Option<PikchrCode>,
+    /// Line of `pikchr_code` picked by clicking the preview
+    pub selected_pikchr_line: Option<usize>,
+    pub modules:              PrologModules,
+    /// From linking against modules, shown while there is no error
+    pub warnings:             Vec<String>,
+    /// Latest input of the tab's renders, a render waiting out the
+    /// debounce gives way to a newer one
+    pub pikchr_input_tx:      watch::Sender<PikchrCode>,
+    pub pikchr_input_rx:      watch::Receiver<PikchrCode>,
+    pub prolog_input_tx:      watch::Sender<Program>,
+    pub prolog_input_rx:      watch::Receiver<Program>,
+    /// Stops the tab's running Prolog render
+    pub prolog_cancel:        CancellationToken,
+}
+
+impl Document {
+    pub fn new(id: DocumentId, text: &str, operating_mode: OperatingMode) -> Self {
+        let content = Content::with_text(text);
+        let (pikchr_input_tx, pikchr_input_rx) = watch::channel(PikchrCode::new(""));
+        let (prolog_input_tx, prolog_input_rx) = watch::channel(Program::default());
+        Self {
+            id,
+            undo_stack: UndoStack::new(content.clone()),
+            content,
+            current_file: None,
+            dirty: true,
+            close_when_saved: false,
+            operating_mode,
+            svg_handle: None,
+            svg_source: None,
+            svg_size: None,
+            preview_view: preview::View::fit(),
+            last_successful: false,
+            last_error: Buffered::new(String::new()),
+            error_line: None,
+            pikchr_code: None,
+            selected_pikchr_line: None,
+            modules: PrologModules::with_disabled(prolog_modules::DEFAULT_DISABLED),
+            warnings: vec![],
+            pikchr_input_tx,
+            pikchr_input_rx,
+            prolog_input_tx,
+            prolog_input_rx,
+            prolog_cancel: CancellationToken::new(),
+        }
+    }
+
+    pub fn open(
+        id: DocumentId,
+        path: &Path,
+        operating_mode: OperatingMode,
+    ) -> Result<Self, ApplicationError> {
+        let mut document = Self::new(id, "", operating_mode);
+        document.current_file = Some(path.to_path_buf());
+        document.reload()?;
+        Ok(document)
+    }
+
+    /// Replaces content with what's on disk, starting a fresh undo history.
+    pub fn reload(&mut self) -> Result<(), ApplicationError> {
+        let Some(path) = &self.current_file else {
+            return Ok(());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|_| ApplicationError::FileLoadFailure(path.clone()))?;
+        self.content = Content::with_text(&text);
+        self.undo_stack = UndoStack::new(self.content.clone());
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Untouched and never saved, fine to replace with a loaded file.
+    pub fn is_blank(&self) -> bool {
+        self.current_file.is_none() && self.undo_stack.undo.len() <= 1
+    }
+
+    /// File name, without the directory.
+    pub fn name(&self) -> String {
+        self.current_file
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(String::from("Unnamed"))
+    }
+
+    /// Label for the tab and window title.
+    pub fn title(&self) -> String {
+        let dirty = if self.dirty { "*" } else { "" };
+        format!("{}{}", self.name(), dirty)
+    }
+
+    /// Pikchr code behind current render.
+    pub fn current_pikchr(&self) -> Option<String> {
+        match self.operating_mode {
+            OperatingMode::PikchrMode => Some(self.content.text()),
+            OperatingMode::PrologMode => self.pikchr_code.clone().map(PikchrCode::into_inner),
+        }
+    }
+
+    /// Error if there is one, otherwise warnings.
+    pub fn info(&self) -> String {
+        let error = self.last_error.get();
+        if !error.is_empty() || self.operating_mode == OperatingMode::PikchrMode {
+            return error;
+        }
+        self.warnings.join("\n")
+    }
+
+    pub fn show_error(&mut self, error: ApplicationError) {
+        if matches!(
+            error,
+            ApplicationError::PikchrPrologError(_) | ApplicationError::PikchrError(_)
+        ) {
+            self.error_line = self.error_source_line(&error);
+        }
+        match error {
+            ApplicationError::PikchrPrologError(render_error) => {
+                self.last_error.set(format!("{:?}", render_error));
+            },
+            ApplicationError::PikchrError(render_error) => {
+                let trimmed = render_error.trim_last_chars(500).trim_last_lines(5);
+
+                self.last_error.set(trimmed);
+            },
+            ApplicationError::PikchrEmpty => (),
+            ApplicationError::Unknown => self.last_error.set(String::from("Unknown error")),
+            ApplicationError::FileLoadFailure(path_buf) => {
+                self.last_error.set(format!(
+                    "Failed to load file: {}",
+                    path_buf.to_string_lossy()
+                ));
+            },
+            ApplicationError::SaveFailure => self.last_error.set(error.to_string()),
+            ApplicationError::ExportFailure(_) => self.last_error.set(error.to_string()),
+        }
+    }
+
+    /// Editor line the error points at, if it can be told.
+    fn error_source_line(&self, error: &ApplicationError) -> Option<usize> {
+        let source = self.content.text();
+        match (error, self.operating_mode) {
+            (ApplicationError::PikchrPrologError(RenderError::PrologError(message)), _) => {
+                prolog::error_line(message).map(|line| heredoc_parser::source_line(&source, line))
+            },
+            (ApplicationError::PikchrError(message), OperatingMode::PikchrMode) => {
+                pikchr::error_line(message)
+            },
+            (ApplicationError::PikchrError(message), OperatingMode::PrologMode) => {
+                let line = pikchr::error_line(message)?;
+                let code = self.pikchr_code.as_ref()?;
+                source_map::prolog_line(code.as_str().lines().nth(line)?, &source)
+            },
+            _ => None,
+        }
+    }
+}
@@ -26,44 +26,41 @@ use crate::{
 const INDENT_SPACES: usize = 4;
 
 pub fn handle(editor: &mut Editor, msg: EditorAction) -> Task<Message> {
+    let content = &mut editor.doc_mut().content;
     match msg {
         EditorAction::Dedent => {
-            let cursor = editor.content.cursor();
+            let cursor = content.cursor();
 
-            let line = editor
-                .content
+            let line = content
                 .line(cursor.position.line)
                 .expect("Line on cursor should exist.");
             let text = String::from(line.text);
             let removals = min(INDENT_SPACES, get_prefix_spaces(&text));
 
-            editor.content.perform(Action::Move(Motion::Home));
+            content.perform(Action::Move(Motion::Home));
             for _ in 0..removals {
-                editor.content.perform(Action::Edit(Edit::Delete));
+                content.perform(Action::Edit(Edit::Delete));
             }
 
-            editor
-                .content
-                .move_to(shift_cursor_cols(cursor, -(removals as isize)));
+            content.move_to(shift_cursor_cols(cursor, -(removals as isize)));
             Task::none()
         },
         EditorAction::Indent => {
-            let orig_cursor = editor.content.cursor();
-            editor.content.perform(Action::Move(Motion::Home));
+            let orig_cursor = content.cursor();
+            content.perform(Action::Move(Motion::Home));
             for _ in 0..INDENT_SPACES {
-                editor.content.perform(Action::Edit(Edit::Insert(' ')));
+                content.perform(Action::Edit(Edit::Insert(' ')));
             }
             let new_cursor = shift_cursor_cols(orig_cursor, INDENT_SPACES as isize);
 
-            editor.content.move_to(new_cursor);
+            content.move_to(new_cursor);
 
             Task::none()
         },
         EditorAction::NewlineIndent => {
-            let cursor = editor.content.cursor();
+            let cursor = content.cursor();
 
-            let line = editor
-                .content
+            let line = content
                 .line(cursor.position.line)
                 .expect("Line on cursor should exist.");
             let text = String::from(line.text);
@@ -85,10 +82,10 @@ pub fn handle(editor: &mut Editor, msg: EditorAction) -> Task<Message> {
                     indent_to = 0
                 }
             }
-            editor.content.perform(Action::Edit(Edit::Enter));
+            content.perform(Action::Edit(Edit::Enter));
 
             for _ in 0..indent_to{
-                editor.content.perform(Action::Edit(Edit::Insert(' ')))
+                content.perform(Action::Edit(Edit::Insert(' ')))
             };
 
             Task::none()
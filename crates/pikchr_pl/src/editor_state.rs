@@ -11,17 +11,30 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
 use iced::{
     keyboard::Modifiers,
-    widget::{pane_grid, svg, text_editor},
+    theme,
+    widget::{pane_grid, scrollable::Viewport},
 };
-use pikchr_pro::types::PikchrCode;
 use serde::{Deserialize, Serialize};
-use tokio::sync::watch;
 
-use crate::{OperatingMode, PaneContent, prolog_modules, undo::UndoStack};
+use crate::{
+    OperatingMode, PaneContent,
+    completion::Completion,
+    definition::References,
+    document::{Document, DocumentId},
+    find::FindState,
+    keybindings::keymap::Keymap,
+    palette::PaletteState,
+    prolog_modules::{self, PrologModules},
+    prolog_source::Indicator,
+    vim::Vim,
+};
 
 pub const INITIAL_CONTENT: &str = r#"diagram -->
   down,
@@ -46,69 +59,249 @@ pub const NEW_CONTENT: &str = r#"diagram -->
 
 
 pub struct Editor {
-    pub modules: prolog_modules::PrologModules,
-    pub pikchr_input_tx: watch::Sender<PikchrCode>,
-    pub pikchr_input_rx: watch::Receiver<PikchrCode>,
-    pub prolog_input_tx: watch::Sender<String>,
-    pub prolog_input_rx: watch::Receiver<String>,
-    pub content: text_editor::Content,
-    pub svg_handle: Option<svg::Handle>,
+    /// Open tabs, never empty
+    pub documents: Vec<Document>,
+    /// Index of the tab shown in `documents`
+    pub active: usize,
+    next_document_id: DocumentId,
+    /// Created on first image copy and kept, some platforms serve clipboard
+    /// contents only while it's alive.
+    pub clipboard: Option<arboard::Clipboard>,
     pub is_compiling: bool,
-    pub last_successful: bool,
-    pub operating_mode: OperatingMode,
     pub modifiers: Modifiers,
-    pub last_error: Buffered<String>,
-    pub current_file: Option<PathBuf>,
+    /// Known once editor content doesn't fit the pane
+    pub editor_viewport: Option<Viewport>,
+    /// Line number typed into "go to line" bar, `None` when it's closed
+    pub goto_line: Option<String>,
+    /// Find and replace bar, `None` when it's closed
+    pub find: Option<FindState>,
+    /// Command palette, `None` when it's closed
+    pub palette: Option<PaletteState>,
+    /// Popup under the cursor
+    pub completion: Completion,
+    /// Predicates folded in the outline
+    pub outline_collapsed: BTreeSet<Indicator>,
+    /// Outcome of the last definition or references lookup
+    pub references: Option<References>,
+    /// Most recent first
+    pub recent_files: Vec<PathBuf>,
+    /// Recent files list opened with the shortcut
+    pub show_recent_files: bool,
+    /// Modules turned off per file, applied when it's opened
+    pub disabled_modules: BTreeMap<PathBuf, Vec<String>>,
+    /// Module shown in modules pane
+    pub viewed_module: Option<String>,
+    /// 0-based line highlighted in the viewed module, e.g. a definition
+    pub viewed_module_line: Option<usize>,
+    /// Picked in modules pane, `None` uses `prolog_modules::default_user_dir`
+    pub configured_modules_dir: Option<PathBuf>,
+    /// From the settings file, reloaded when it changes
+    pub keymap: Keymap,
+    /// Modal editing state, used when `keymap.modal` is on
+    pub vim: Vim,
     pub show_debug: bool,
-    pub pikchr_code: Option<PikchrCode>,
-    pub dirty: bool,
-    pub undo_stack: UndoStack,
+    /// Light or dark, as the system is
+    pub theme_mode: theme::Mode,
     pub panes: pane_grid::State<PaneContent>,
     pub file_watch_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OpenFile {
+    pub path: PathBuf,
+    pub operating_mode: OperatingMode,
+    pub preview_zoom: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct EditorSaveState {
+    /// Tabs backed by a file, unnamed ones aren't restored
+    pub open_files: Vec<OpenFile>,
+    /// Index into `open_files`
+    pub active_file: Option<usize>,
+    pub file_watch_mode: bool,
+    pub show_debug: bool,
+    /// Mode of the active tab, used when there are no files to restore
+    pub operating_mode: OperatingMode,
+    pub recent_files: Vec<PathBuf>,
+    pub disabled_modules: BTreeMap<PathBuf, Vec<String>>,
+    pub configured_modules_dir: Option<PathBuf>,
+}
+
+/// Save state from before it was versioned, a single file and no tabs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SaveStateV0 {
     pub current_file: Option<PathBuf>,
     pub file_watch_mode: bool,
     pub show_debug: bool,
     pub operating_mode: OperatingMode,
 }
 
+impl From<SaveStateV0> for EditorSaveState {
+    fn from(state: SaveStateV0) -> Self {
+        let open_files: Vec<OpenFile> = state
+            .current_file
+            .iter()
+            .map(|path| OpenFile {
+                path: path.clone(),
+                operating_mode: state.operating_mode,
+                preview_zoom: None,
+            })
+            .collect();
+        Self {
+            active_file: (!open_files.is_empty()).then_some(0),
+            open_files,
+            file_watch_mode: state.file_watch_mode,
+            show_debug: state.show_debug,
+            operating_mode: state.operating_mode,
+            recent_files: state.current_file.into_iter().collect(),
+            disabled_modules: BTreeMap::new(),
+            configured_modules_dir: None,
+        }
+    }
+}
+
 impl Default for Editor {
     fn default() -> Self {
-        let (piktx, pikrx) = watch::channel(PikchrCode::new(""));
-        let (prtx, prrx) = watch::channel(String::new());
-        let content = text_editor::Content::with_text(INITIAL_CONTENT);
 
         let (mut pane_state, main_pane) = pane_grid::State::new(PaneContent::Editor);
         pane_state.split(pane_grid::Axis::Vertical, main_pane, PaneContent::Preview);
-        let mut modules = prolog_modules::PrologModules::new();
-        modules.disable("testing");
         Self {
-            modules,
-            undo_stack: UndoStack::new(content.clone()),
-            pikchr_input_tx: piktx,
-            pikchr_input_rx: pikrx,
-            prolog_input_tx: prtx,
-            prolog_input_rx: prrx,
-            svg_handle: None,
+            documents: vec![Document::new(0, INITIAL_CONTENT, OperatingMode::PrologMode)],
+            active: 0,
+            next_document_id: 1,
+            clipboard: None,
             is_compiling: false,
-            last_successful: false,
-            operating_mode: OperatingMode::PrologMode,
             modifiers: Modifiers::default(),
-            last_error: Buffered::new(String::new()),
-            current_file: None,
-            dirty: true,
+            editor_viewport: None,
+            goto_line: None,
+            find: None,
+            palette: None,
+            completion: Completion::default(),
+            outline_collapsed: BTreeSet::new(),
+            references: None,
+            recent_files: Vec::new(),
+            show_recent_files: false,
+            disabled_modules: BTreeMap::new(),
+            viewed_module: None,
+            viewed_module_line: None,
+            configured_modules_dir: None,
+            keymap: Keymap::load(),
+            vim: Vim::default(),
             show_debug: false,
-            pikchr_code: None,
+            theme_mode: theme::Mode::default(),
             file_watch_mode: false,
-            content,
             panes: pane_state,
         }
     }
 }
 
+impl Editor {
+    pub fn doc(&self) -> &Document {
+        &self.documents[self.active]
+    }
+
+    pub fn doc_mut(&mut self) -> &mut Document {
+        &mut self.documents[self.active]
+    }
+
+    /// Document a finished render belongs to, `None` if its tab is closed.
+    pub fn document_mut(&mut self, id: DocumentId) -> Option<&mut Document> {
+        self.documents.iter_mut().find(|document| document.id == id)
+    }
+
+    /// Tab of a document, `None` if it's closed.
+    pub fn document_index(&self, id: DocumentId) -> Option<usize> {
+        self.documents.iter().position(|document| document.id == id)
+    }
+
+    pub fn next_document_id(&mut self) -> DocumentId {
+        let id = self.next_document_id;
+        self.next_document_id += 1;
+        id
+    }
+
+    pub fn user_modules_dir(&self) -> Option<PathBuf> {
+        self.configured_modules_dir
+            .clone()
+            .or_else(prolog_modules::default_user_dir)
+    }
+
+    /// Loads modules available to the document at `index`, with selection
+    /// saved for its file.
+    pub fn load_modules(&mut self, index: usize) {
+        let user_dir = self.user_modules_dir();
+        let document = &mut self.documents[index];
+        let file = document.current_file.as_deref();
+        let disabled = file
+            .and_then(|path| self.disabled_modules.get(path))
+            .cloned()
+            .unwrap_or_else(|| document.modules.disabled());
+        document.modules = PrologModules::load(user_dir.as_deref(), file);
+        document.modules.set_disabled(disabled);
+    }
+
+    /// Picks up changes in module directories of all documents.
+    pub fn reload_modules(&mut self) {
+        let user_dir = self.user_modules_dir();
+        for document in &mut self.documents {
+            let file = document.current_file.as_deref();
+            document.modules.reload(user_dir.as_deref(), file);
+        }
+    }
+
+    /// Existing directories modules are loaded from.
+    pub fn module_dirs(&self) -> BTreeSet<PathBuf> {
+        self.user_modules_dir()
+            .into_iter()
+            .chain(
+                self.documents
+                    .iter()
+                    .filter_map(|document| document.current_file.as_deref())
+                    .filter_map(prolog_modules::project_dir),
+            )
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+    /// Keeps module selection of the document at `index` for its file.
+    pub fn remember_module_selection(&mut self, index: usize) {
+        let document = &self.documents[index];
+        if let Some(path) = document.current_file.clone() {
+            let disabled = document.modules.disabled();
+            self.disabled_modules.insert(path, disabled);
+        }
+    }
+
+    /// Adds a tab after the active one and switches to it. A blank active
+    /// tab is replaced instead.
+    pub fn push_document(&mut self, document: Document) {
+        if self.doc().is_blank() {
+            *self.doc_mut() = document;
+        } else {
+            self.active += 1;
+            self.documents.insert(self.active, document);
+        }
+    }
+
+    /// Closes tab at `index`, the last one is replaced with a new document.
+    pub fn close_document(&mut self, index: usize) {
+        if index >= self.documents.len() {
+            return;
+        }
+        let closed = self.documents.remove(index);
+        closed.prolog_cancel.cancel();
+        if self.documents.is_empty() {
+            let id = self.next_document_id();
+            self.documents
+                .push(Document::new(id, NEW_CONTENT, closed.operating_mode));
+        }
+        if index < self.active || self.active >= self.documents.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+    }
+}
+
 pub struct Buffered<T: Clone> {
     current: T,
     cached: T,
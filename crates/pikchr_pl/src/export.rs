@@ -0,0 +1,125 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fmt::Display, path::Path};
+
+use resvg::{tiny_skia, usvg};
+use thiserror::Error;
+
+use crate::SPACE_MONO_BYTES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Svg,
+    /// PNG rendered at given scale
+    Png(u32),
+    Pikchr,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 5] = [
+        ExportFormat::Svg,
+        ExportFormat::Png(1),
+        ExportFormat::Png(2),
+        ExportFormat::Png(4),
+        ExportFormat::Pikchr,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Png(_) => "png",
+            ExportFormat::Pikchr => "pik",
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Svg => write!(f, "SVG"),
+            ExportFormat::Png(scale) => write!(f, "PNG ({}x)", scale),
+            ExportFormat::Pikchr => write!(f, "Pikchr Source"),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum ExportError {
+    #[error("Nothing to export, diagram wasn't rendered yet")]
+    NothingToExport,
+    #[error("Can't parse SVG: {0}")]
+    InvalidSvg(String),
+    #[error("Can't render PNG at this scale")]
+    RenderFailure,
+    #[error("Can't write file: {0}")]
+    WriteFailure(String),
+    #[error("Clipboard unavailable: {0}")]
+    ClipboardFailure(String),
+}
+
+pub fn export(
+    path: &Path,
+    format: ExportFormat,
+    svg: Option<&str>,
+    pikchr: Option<&str>,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::Svg => write_text(path, svg),
+        ExportFormat::Pikchr => write_text(path, pikchr),
+        ExportFormat::Png(scale) => {
+            let svg = svg.ok_or(ExportError::NothingToExport)?;
+            write_png(path, svg, scale as f32)
+        },
+    }
+}
+
+fn write_text(path: &Path, text: Option<&str>) -> Result<(), ExportError> {
+    let text = text.ok_or(ExportError::NothingToExport)?;
+    std::fs::write(path, text).map_err(|e| ExportError::WriteFailure(e.to_string()))
+}
+
+fn write_png(path: &Path, svg: &str, scale: f32) -> Result<(), ExportError> {
+    rasterize(svg, scale)?
+        .save_png(path)
+        .map_err(|e| ExportError::WriteFailure(e.to_string()))
+}
+
+/// Renders SVG onto opaque white pixmap, `scale` times its natural size.
+pub fn rasterize(svg: &str, scale: f32) -> Result<tiny_skia::Pixmap, ExportError> {
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    // Injected style refers to it, so it has to be there
+    options
+        .fontdb_mut()
+        .load_font_data(SPACE_MONO_BYTES.to_vec());
+
+    let tree = usvg::Tree::from_data(svg.as_bytes(), &options)
+        .map_err(|e| ExportError::InvalidSvg(e.to_string()))?;
+    let size = tree
+        .size()
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or(ExportError::RenderFailure)?;
+
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size.width(), size.height()).ok_or(ExportError::RenderFailure)?;
+    // Same as preview background
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap)
+}
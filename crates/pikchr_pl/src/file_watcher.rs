@@ -24,13 +24,45 @@ use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 
 use crate::messages::Message;
 
+/// What is being watched, picks the message sent on change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Watched {
+    LoadedFile,
+    ModuleDir,
+    SettingsDir,
+}
+
+impl Watched {
+    fn message(self) -> Message {
+        match self {
+            Watched::LoadedFile => Message::LoadedFileChanged,
+            Watched::ModuleDir => Message::ModulesChanged,
+            Watched::SettingsDir => Message::SettingsChanged,
+        }
+    }
+}
+
 pub fn file_watcher(path: &Path) -> Subscription<Message> {
+    watch(path, Watched::LoadedFile)
+}
+
+/// Watches `.pl` files directly in `dir`, which has to exist.
+pub fn module_dir_watcher(dir: &Path) -> Subscription<Message> {
+    watch(dir, Watched::ModuleDir)
+}
+
+/// Watches the config directory for the settings file, `dir` has to exist.
+pub fn settings_watcher(dir: &Path) -> Subscription<Message> {
+    watch(dir, Watched::SettingsDir)
+}
+
+fn watch(path: &Path, watched: Watched) -> Subscription<Message> {
     let s = path.to_string_lossy().into_owned();
-    Subscription::run_with(s, watch_stream)
+    Subscription::run_with((s, watched), watch_stream)
 }
-#[allow(clippy::ptr_arg)]
-fn watch_stream(path: &String) -> BoxStream<'static, Message> {
+fn watch_stream((path, watched): &(String, Watched)) -> BoxStream<'static, Message> {
     let owned_path = path.clone();
+    let watched = *watched;
     Box::pin(iced_stream::channel(
         100,
         move |mut output: Sender<Message>| async move {
@@ -50,7 +82,7 @@ fn watch_stream(path: &String) -> BoxStream<'static, Message> {
             while let Some(res) = rx.next().await {
                 match res {
                     Ok(_event) => {
-                        let _ = output.send(Message::LoadedFileChanged).await;
+                        let _ = output.send(watched.message()).await;
                     },
                     Err(e) => eprintln!("Watch error: {:?}", e),
                 }
@@ -0,0 +1,534 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Find and replace bar over the editor.
+//!
+//! Matches never span lines, `^` and `$` anchor to line boundaries. Plain
+//! queries go through the same regex engine, escaped.
+
+use std::{ops::Range, sync::Arc};
+
+use iced::{
+    Alignment, Element, Task,
+    widget::{
+        button, checkbox, column, operation, row, text,
+        text_editor::{Action, Content, Cursor, Edit, Position},
+        text_input,
+    },
+};
+use regex::{Captures, Regex, RegexBuilder};
+
+use crate::{
+    editor_state::Editor,
+    messages::{FindAction, Message},
+};
+
+pub const QUERY_ID: &str = "find_query";
+const TEXT_SIZE: f32 = 12.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct FindState {
+    pub query:          String,
+    pub replacement:    String,
+    pub case_sensitive: bool,
+    pub regex:          bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub line:  usize,
+    /// Byte range within the line
+    pub range: Range<usize>,
+}
+
+impl Match {
+    fn start(&self) -> Position {
+        Position {
+            line:   self.line,
+            column: self.range.start,
+        }
+    }
+
+    fn end(&self) -> Position {
+        Position {
+            line:   self.line,
+            column: self.range.end,
+        }
+    }
+}
+
+impl FindState {
+    /// `None` for an empty query.
+    fn matcher(&self) -> Option<Result<Regex, regex::Error>> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        Some(
+            RegexBuilder::new(&pattern)
+                .case_insensitive(!self.case_sensitive)
+                .build(),
+        )
+    }
+
+    /// All non-empty matches in `content`, in order.
+    pub fn matches(&self, content: &Content) -> Result<Vec<Match>, regex::Error> {
+        let Some(matcher) = self.matcher().transpose()? else {
+            return Ok(vec![]);
+        };
+        Ok(content
+            .lines()
+            .enumerate()
+            .flat_map(|(line, text)| {
+                matcher
+                    .find_iter(&text.text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| Match {
+                        line,
+                        range: m.range(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    /// Replacement text for a match, with `$1` style groups expanded in
+    /// regex mode.
+    fn replace(&self, captures: &Captures) -> String {
+        if self.regex {
+            let mut replacement = String::new();
+            captures.expand(&self.replacement, &mut replacement);
+            replacement
+        } else {
+            self.replacement.clone()
+        }
+    }
+
+    /// Summary for the bar, e.g. "2 of 5".
+    pub fn status(&self, content: &Content) -> String {
+        match self.matches(content) {
+            Err(_) => String::from("Invalid regex"),
+            Ok(matches) if matches.is_empty() => String::from("No matches"),
+            Ok(matches) => match current(content, &matches) {
+                Some(index) => format!("{} of {}", index + 1, matches.len()),
+                None => format!("{} matches", matches.len()),
+            },
+        }
+    }
+}
+
+/// Index of the match that is currently selected.
+fn current(content: &Content, matches: &[Match]) -> Option<usize> {
+    let cursor = content.cursor();
+    let selection = cursor.selection?;
+    matches
+        .iter()
+        .position(|m| m.start() == selection && m.end() == cursor.position)
+}
+
+fn select(content: &mut Content, found: &Match) {
+    content.move_to(Cursor {
+        position:  found.end(),
+        selection: Some(found.start()),
+    });
+}
+
+/// Start of the selection, or the cursor when nothing is selected.
+fn selection_start(content: &Content) -> Position {
+    let cursor = content.cursor();
+    match cursor.selection {
+        Some(selection) if order(selection) < order(cursor.position) => selection,
+        _ => cursor.position,
+    }
+}
+
+/// `Position` isn't `Ord`
+fn order(position: Position) -> (usize, usize) {
+    (position.line, position.column)
+}
+
+fn first_from(matches: &[Match], from: Position) -> Option<&Match> {
+    matches
+        .iter()
+        .find(|m| order(m.start()) >= order(from))
+        .or_else(|| matches.first())
+}
+
+fn last_before(matches: &[Match], before: Position) -> Option<&Match> {
+    matches
+        .iter()
+        .rev()
+        .find(|m| order(m.end()) <= order(before))
+        .or_else(|| matches.last())
+}
+
+/// Opens the bar, prefilled with a single line selection.
+fn open(editor: &mut Editor) -> Task<Message> {
+    let query = editor
+        .doc()
+        .content
+        .selection()
+        .filter(|selection| !selection.contains('\n'))
+        .or_else(|| editor.find.as_ref().map(|find| find.query.clone()))
+        .unwrap_or_default();
+    editor.find.get_or_insert_default().query = query;
+    Task::batch([operation::focus(QUERY_ID), operation::select_all(QUERY_ID)])
+}
+
+pub fn handle(editor: &mut Editor, action: FindAction) -> Task<Message> {
+    if let FindAction::Requested = action {
+        return open(editor);
+    }
+    let Some(find) = editor.find.as_mut() else {
+        return Task::none();
+    };
+    let document = &mut editor.documents[editor.active];
+    match action {
+        FindAction::Requested => Task::none(),
+        FindAction::QueryChanged(query) => {
+            find.query = query;
+            // Incremental: stay on the current match while it still matches
+            let from = selection_start(&document.content);
+            find_from(editor, from)
+        },
+        FindAction::ReplacementChanged(replacement) => {
+            find.replacement = replacement;
+            Task::none()
+        },
+        FindAction::CaseSensitiveToggled(case_sensitive) => {
+            find.case_sensitive = case_sensitive;
+            let from = selection_start(&document.content);
+            find_from(editor, from)
+        },
+        FindAction::RegexToggled(regex) => {
+            find.regex = regex;
+            let from = selection_start(&document.content);
+            find_from(editor, from)
+        },
+        FindAction::Next => {
+            let from = document.content.cursor().position;
+            find_from(editor, from)
+        },
+        FindAction::Previous => {
+            let Ok(matches) = find.matches(&document.content) else {
+                return Task::none();
+            };
+            match last_before(&matches, selection_start(&document.content)) {
+                Some(found) => {
+                    select(&mut document.content, found);
+                    editor.scroll_to_cursor()
+                },
+                None => Task::none(),
+            }
+        },
+        FindAction::Replace => {
+            let (Some(Ok(matcher)), Ok(matches)) = (find.matcher(), find.matches(&document.content))
+            else {
+                return Task::none();
+            };
+            let Some(index) = current(&document.content, &matches) else {
+                // Nothing selected yet, show what would be replaced first
+                let from = document.content.cursor().position;
+                return find_from(editor, from);
+            };
+            let found = &matches[index];
+            let line = document
+                .content
+                .line(found.line)
+                .expect("Matched line should exist.");
+            // Matched again within the whole line, anchors and word
+            // boundaries depend on what's around the match
+            let captures = matcher
+                .captures_at(&line.text, found.range.start)
+                .expect("Match should still be found.");
+            let replacement = find.replace(&captures);
+
+            document.undo_stack.push(&document.content);
+            document.dirty = true;
+            document
+                .content
+                .perform(Action::Edit(Edit::Paste(Arc::new(replacement))));
+            let from = document.content.cursor().position;
+            Task::batch([Task::done(Message::RunLogic), find_from(editor, from)])
+        },
+        FindAction::ReplaceAll => {
+            let Some(Ok(matcher)) = find.matcher() else {
+                return Task::none();
+            };
+            let mut replaced = 0;
+            let text: String = document
+                .content
+                .lines()
+                .map(|line| {
+                    let text = matcher.replace_all(&line.text, |captures: &Captures| {
+                        let found = captures.get(0).expect("Group 0 is the whole match");
+                        if found.is_empty() {
+                            return String::new();
+                        }
+                        replaced += 1;
+                        find.replace(captures)
+                    });
+                    format!("{}{}", text, line.ending.as_str())
+                })
+                .collect();
+            if replaced == 0 {
+                return Task::none();
+            }
+
+            let position = document.content.cursor().position;
+            document.undo_stack.push(&document.content);
+            document.dirty = true;
+            document.content = Content::with_text(&text);
+            let line = position.line.min(document.content.line_count() - 1);
+            document.content.move_to(Cursor {
+                position:  Position { line, column: 0 },
+                selection: None,
+            });
+            Task::batch([Task::done(Message::RunLogic), editor.scroll_to_cursor()])
+        },
+    }
+}
+
+/// Selects the first match at or after `from`, wrapping around.
+fn find_from(editor: &mut Editor, from: Position) -> Task<Message> {
+    let Some(Ok(matches)) = editor
+        .find
+        .as_ref()
+        .map(|find| find.matches(&editor.doc().content))
+    else {
+        return Task::none();
+    };
+    match first_from(&matches, from) {
+        Some(found) => {
+            select(&mut editor.doc_mut().content, found);
+            editor.scroll_to_cursor()
+        },
+        None => Task::none(),
+    }
+}
+
+/// Query and replacement rows with their buttons.
+pub fn view<'a>(find: &'a FindState, content: &Content) -> Element<'a, Message> {
+    let small_button = |label| button(text(label).size(TEXT_SIZE));
+
+    column![
+        row![
+            text_input("Find", &find.query)
+                .id(QUERY_ID)
+                .on_input(|query| Message::Find(FindAction::QueryChanged(query)))
+                .on_submit(Message::Find(FindAction::Next))
+                .size(TEXT_SIZE),
+            text(find.status(content)).size(TEXT_SIZE),
+            small_button("Previous").on_press(Message::Find(FindAction::Previous)),
+            small_button("Next").on_press(Message::Find(FindAction::Next)),
+            checkbox(find.case_sensitive)
+                .label("Match case")
+                .on_toggle(|checked| Message::Find(FindAction::CaseSensitiveToggled(checked)))
+                .text_size(TEXT_SIZE),
+            checkbox(find.regex)
+                .label("Regex")
+                .on_toggle(|checked| Message::Find(FindAction::RegexToggled(checked)))
+                .text_size(TEXT_SIZE),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(5),
+        row![
+            text_input("Replace", &find.replacement)
+                .on_input(|replacement| Message::Find(FindAction::ReplacementChanged(replacement)))
+                .on_submit(Message::Find(FindAction::Replace))
+                .size(TEXT_SIZE),
+            small_button("Replace").on_press(Message::Find(FindAction::Replace)),
+            small_button("Replace all").on_press(Message::Find(FindAction::ReplaceAll)),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(5),
+    ]
+    .spacing(5)
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OperatingMode, document::Document};
+
+    fn editor(text: &str, find: FindState) -> Editor {
+        let mut editor = Editor::default();
+        editor.documents = vec![Document::new(0, text, OperatingMode::PrologMode)];
+        editor.find = Some(find);
+        editor
+    }
+
+    fn query(query: &str) -> FindState {
+        FindState {
+            query: query.to_string(),
+            ..FindState::default()
+        }
+    }
+
+    fn selected(editor: &Editor) -> Option<String> {
+        editor.doc().content.selection()
+    }
+
+    fn selected_match(editor: &Editor) -> Option<usize> {
+        let find = editor.find.as_ref().unwrap();
+        let content = &editor.doc().content;
+        current(content, &find.matches(content).unwrap())
+    }
+
+    #[test]
+    fn matches_stay_within_lines() {
+        let content = Content::with_text("ab ab\nAB\nb");
+        let matches = query("ab").matches(&content).unwrap();
+        assert_eq!(
+            matches,
+            vec![
+                Match {
+                    line:  0,
+                    range: 0..2,
+                },
+                Match {
+                    line:  0,
+                    range: 3..5,
+                },
+                Match {
+                    line:  1,
+                    range: 0..2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_follow_options() {
+        let content = Content::with_text("a.b axb\nA.B");
+        let case_sensitive = FindState {
+            case_sensitive: true,
+            ..query("a.b")
+        };
+        assert_eq!(case_sensitive.matches(&content).unwrap().len(), 1);
+        let regex = FindState {
+            regex: true,
+            ..query("a.b")
+        };
+        assert_eq!(regex.matches(&content).unwrap().len(), 3);
+        let anchored = FindState {
+            regex: true,
+            ..query("^a")
+        };
+        assert_eq!(anchored.matches(&content).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn empty_query_and_empty_matches_find_nothing() {
+        let content = Content::with_text("abc");
+        assert!(query("").matches(&content).unwrap().is_empty());
+        let empty = FindState {
+            regex: true,
+            ..query("x*")
+        };
+        assert!(empty.matches(&content).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_regex_is_reported() {
+        let content = Content::with_text("abc");
+        let invalid = FindState {
+            regex: true,
+            ..query("(")
+        };
+        assert!(invalid.matches(&content).is_err());
+        assert_eq!(invalid.status(&content), "Invalid regex");
+    }
+
+    #[test]
+    fn next_and_previous_wrap_around() {
+        let mut editor = editor("x\nx\nx", query("x"));
+        let _ = handle(&mut editor, FindAction::Next);
+        assert_eq!(selected_match(&editor), Some(0));
+        let _ = handle(&mut editor, FindAction::Next);
+        let _ = handle(&mut editor, FindAction::Next);
+        assert_eq!(selected_match(&editor), Some(2));
+        let _ = handle(&mut editor, FindAction::Next);
+        assert_eq!(selected_match(&editor), Some(0));
+        let _ = handle(&mut editor, FindAction::Previous);
+        assert_eq!(selected_match(&editor), Some(2));
+        let _ = handle(&mut editor, FindAction::Previous);
+        assert_eq!(selected_match(&editor), Some(1));
+    }
+
+    #[test]
+    fn replace_selects_first_then_replaces() {
+        let mut editor = editor(
+            "ab ab",
+            FindState {
+                replacement: String::from("c"),
+                ..query("ab")
+            },
+        );
+        let _ = handle(&mut editor, FindAction::Replace);
+        assert_eq!(editor.doc().content.text(), "ab ab");
+        assert_eq!(selected(&editor).as_deref(), Some("ab"));
+        let _ = handle(&mut editor, FindAction::Replace);
+        assert_eq!(editor.doc().content.text(), "c ab");
+        assert_eq!(selected_match(&editor), Some(0));
+        assert!(editor.doc().dirty);
+    }
+
+    #[test]
+    fn replace_expands_groups_of_the_real_match() {
+        // Alone the matched `ab` starts at a word boundary, `\B` fails
+        let mut editor = editor(
+            "xab",
+            FindState {
+                regex:       true,
+                replacement: String::from("[$1]"),
+                ..query(r"\B(a)b")
+            },
+        );
+        let _ = handle(&mut editor, FindAction::Replace);
+        let _ = handle(&mut editor, FindAction::Replace);
+        assert_eq!(editor.doc().content.text(), "x[a]");
+    }
+
+    #[test]
+    fn replace_all_expands_groups() {
+        let mut editor = editor(
+            "a1 b2\nc3",
+            FindState {
+                regex:       true,
+                replacement: String::from("$2$1"),
+                ..query(r"(\w)(\d)")
+            },
+        );
+        let _ = handle(&mut editor, FindAction::ReplaceAll);
+        assert_eq!(editor.doc().content.text(), "1a 2b\n3c");
+    }
+
+    #[test]
+    fn replace_all_keeps_dollars_in_plain_mode() {
+        let mut editor = editor(
+            "a a",
+            FindState {
+                replacement: String::from("$1"),
+                ..query("a")
+            },
+        );
+        let _ = handle(&mut editor, FindAction::ReplaceAll);
+        assert_eq!(editor.doc().content.text(), "$1 $1");
+    }
+}
@@ -0,0 +1,154 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Column next to the editor, lined up with its lines.
+//!
+//! Editor grows to fit its content and both sit in one scrollable, that's
+//! the only way to keep them in sync as `text_editor` doesn't expose its
+//! scroll offset. It also means the scrollable has to follow the cursor.
+
+use iced::{
+    Color, Element, Font, Point, Task,
+    font::Weight,
+    widget::{
+        container,
+        operation::{self, AbsoluteOffset},
+        rich_text,
+        scrollable::Viewport,
+        span,
+        text::Span,
+        text_editor::Content,
+    },
+};
+
+use crate::messages::Message;
+
+pub const SCROLL_ID: &str = "editor_scroll";
+pub const TEXT_SIZE: f32 = 12.0;
+/// `text_editor` default padding
+pub const PADDING: f32 = 5.0;
+/// Between the gutter and the editor
+pub const SPACING: f32 = 5.0;
+/// `LineHeight::default()` is 1.3 of the text size
+const LINE_HEIGHT: f32 = TEXT_SIZE * 1.3;
+/// Advance of `Font::MONOSPACE`, close enough to place popups
+const CHAR_WIDTH: f32 = TEXT_SIZE * 0.6;
+
+const ERROR_MARKER: &str = "●";
+const ERROR_COLOR: Color = Color::from_rgb8(233, 51, 58);
+const NUMBER_COLOR: Color = Color::from_rgb(0.5, 0.5, 0.5);
+
+/// Line numbers, with the cursor line in bold and the error line marked.
+pub fn view<'a>(
+    line_count: usize,
+    cursor_line: usize,
+    error_line: Option<usize>,
+) -> Element<'a, Message> {
+    let width = line_count.max(1).to_string().len();
+
+    let spans: Vec<Span<'a, (), Font>> = (0..line_count.max(1))
+        .flat_map(|line| {
+            let number = span(format!("{:>width$}", line + 1));
+            let number = if line == cursor_line {
+                number.font(Font {
+                    weight: Weight::Bold,
+                    ..Font::MONOSPACE
+                })
+            } else {
+                number.color(NUMBER_COLOR)
+            };
+            let marker = if error_line == Some(line) {
+                span(format!("{}\n", ERROR_MARKER)).color(ERROR_COLOR)
+            } else {
+                span(" \n")
+            };
+            [number, marker]
+        })
+        .collect();
+
+    container(rich_text(spans).size(TEXT_SIZE).font(Font::MONOSPACE))
+        .padding([PADDING, 0.0])
+        .into()
+}
+
+/// Numbers plus the error marker.
+pub fn width(line_count: usize) -> f32 {
+    (line_count.max(1).to_string().len() + 1) as f32 * CHAR_WIDTH
+}
+
+/// Editor width that fits the longest line, and the cursor after it.
+pub fn content_width(content: &Content) -> f32 {
+    let longest = (0..content.line_count())
+        .filter_map(|line| content.line(line).map(|line| line.text.chars().count()))
+        .max()
+        .unwrap_or(0);
+    (longest + 1) as f32 * CHAR_WIDTH + 2.0 * PADDING
+}
+
+/// Bottom left of the character at `line` and `column` (in chars), from the
+/// top left of the gutter.
+pub fn below(line_count: usize, line: usize, column: usize) -> Point {
+    Point::new(
+        width(line_count) + SPACING + PADDING + column as f32 * CHAR_WIDTH,
+        PADDING + (line + 1) as f32 * LINE_HEIGHT,
+    )
+}
+
+/// Scrolls the editor so the character at `line` and `column` (in chars) is
+/// visible, if it isn't already.
+pub fn scroll_to_cursor(
+    viewport: Option<Viewport>,
+    line_count: usize,
+    line: usize,
+    column: usize,
+) -> Task<Message> {
+    let Some(viewport) = viewport else {
+        // Content fits, nothing to scroll
+        return Task::none();
+    };
+    let offset = viewport.absolute_offset();
+    let bounds = viewport.bounds();
+
+    let top = PADDING + line as f32 * LINE_HEIGHT;
+    let bottom = top + LINE_HEIGHT + PADDING;
+    let y = if top < offset.y + PADDING {
+        Some(top - PADDING)
+    } else if bottom > offset.y + bounds.height {
+        Some(bottom - bounds.height)
+    } else {
+        None
+    };
+
+    let left = below(line_count, line, column).x;
+    let right = left + CHAR_WIDTH + PADDING;
+    let x = if left < offset.x {
+        // Back to the start of the line if it fits, else just the cursor
+        let start = width(line_count) + SPACING;
+        Some(if right - start < bounds.width { 0.0 } else { left - PADDING })
+    } else if right > offset.x + bounds.width {
+        Some(right - bounds.width)
+    } else {
+        None
+    };
+
+    if x.is_none() && y.is_none() {
+        return Task::none();
+    }
+    operation::scroll_to(
+        SCROLL_ID,
+        AbsoluteOffset {
+            x: x.map(|x| x.max(0.0)),
+            y: y.map(|y| y.max(0.0)),
+        },
+    )
+}
@@ -18,3 +18,63 @@ pub fn transform_heredoc(input: &str) -> String {
 
     output
 }
+
+/// Maps line of `transform_heredoc` output back to the line of `input`.
+///
+/// Heredocs are collapsed into a single line, so every line after one
+/// is shifted by the number of lines it spanned.
+pub fn source_line(input: &str, transformed_line: usize) -> usize {
+    let mut source = 0;
+    let mut transformed = 0;
+
+    for (i, part) in input.split("===").enumerate() {
+        let newlines = part.matches('\n').count();
+        if i % 2 == 1 {
+            source += newlines;
+            continue;
+        }
+        if transformed + newlines >= transformed_line {
+            return source + (transformed_line - transformed);
+        }
+        source += newlines;
+        transformed += newlines;
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_without_heredocs_stay() {
+        let input = "a\nb\nc";
+        assert_eq!(source_line(input, 0), 0);
+        assert_eq!(source_line(input, 2), 2);
+    }
+
+    #[test]
+    fn lines_after_heredoc_are_shifted() {
+        let input = "a\n===\nx\ny\n===\nb\nc";
+        // Transformed: `a`, the heredoc as one string, `b`, `c`
+        assert_eq!(transform_heredoc(input).lines().count(), 4);
+        assert_eq!(source_line(input, 1), 1);
+        assert_eq!(source_line(input, 2), 5);
+        assert_eq!(source_line(input, 3), 6);
+    }
+
+    #[test]
+    fn shifts_add_up_over_heredocs() {
+        let input = "===\nx\n===\na\n===\ny\nz\n===\nb";
+        assert_eq!(source_line(input, 1), 3);
+        assert_eq!(source_line(input, 2), 4);
+        assert_eq!(source_line(input, 3), 8);
+    }
+
+    #[test]
+    fn heredoc_on_one_line_shifts_nothing() {
+        let input = "label(===x===).\nb";
+        assert_eq!(source_line(input, 1), 1);
+    }
+}
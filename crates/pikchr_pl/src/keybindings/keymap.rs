@@ -0,0 +1,545 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Key chords to commands, read from `keybindings.toml` in the config
+//! directory on top of a preset.
+
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use directories::ProjectDirs;
+use iced::{
+    keyboard::{Key, Modifiers, key::Named},
+    widget::text_editor::{Action, Edit, Motion},
+};
+use serde::Deserialize;
+
+use crate::{
+    clipboard::CopyFormat,
+    export::ExportFormat,
+    OperatingMode,
+    messages::{
+        CompletionAction, DefinitionAction, EditorAction, FindAction, Message, PaletteAction,
+    },
+};
+
+pub const SETTINGS_FILE: &str = "keybindings.toml";
+/// Used without a settings file, or when it can't be read
+const FALLBACK_PRESET: &str = "emacs";
+const PRESETS: &[(&str, &str)] = &[
+    ("default", include_str!("../../keymaps/default.toml")),
+    ("emacs", include_str!("../../keymaps/emacs.toml")),
+    ("vim", include_str!("../../keymaps/vim.toml")),
+];
+
+/// What a chord can be bound to, as written in the settings file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    /// Drops the preset binding of the chord
+    Unbind,
+    Undo,
+    Redo,
+    New,
+    Open,
+    Save,
+    SaveAs,
+    RecentFiles,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    /// 1-based, as on the keyboard
+    SelectTab(usize),
+    Find,
+    GoToLine,
+    JumpToError,
+    GoToDefinition,
+    FindReferences,
+    ToggleDebug,
+    ToggleFileWatch,
+    ToggleModules,
+    ToggleOutline,
+    #[serde(rename = "command_palette")]
+    Palette,
+    /// Opens the completion popup
+    Complete,
+    PrologMode,
+    PikchrMode,
+    CopyImage,
+    CopySvg,
+    CopyPikchr,
+    ExportSvg,
+    /// PNG at given scale
+    ExportPng(u32),
+    ExportPikchr,
+    Indent,
+    Dedent,
+    NewlineIndent,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveHome,
+    MoveEnd,
+    MoveWordLeft,
+    MoveWordRight,
+    SelectWordLeft,
+    SelectWordRight,
+    DeleteWord,
+    DeleteLine,
+    /// Deletes to the end of line
+    KillLine,
+    /// Inserts a line above the cursor
+    OpenLine,
+}
+
+impl Command {
+    /// Offered in the command palette, with their labels.
+    pub const PALETTE: &[(Command, &str)] = &[
+        (Command::New, "New file"),
+        (Command::Open, "Open file"),
+        (Command::RecentFiles, "Recent files"),
+        (Command::Save, "Save"),
+        (Command::SaveAs, "Save as"),
+        (Command::CloseTab, "Close tab"),
+        (Command::NextTab, "Next tab"),
+        (Command::PreviousTab, "Previous tab"),
+        (Command::Undo, "Undo"),
+        (Command::Redo, "Redo"),
+        (Command::Find, "Find and replace"),
+        (Command::GoToLine, "Go to line"),
+        (Command::JumpToError, "Jump to error"),
+        (Command::GoToDefinition, "Go to definition"),
+        (Command::FindReferences, "Find references"),
+        (Command::PrologMode, "Switch to Prolog mode"),
+        (Command::PikchrMode, "Switch to Pikchr mode"),
+        (Command::ToggleDebug, "Toggle debug overlay"),
+        (Command::ToggleFileWatch, "Toggle file watch mode"),
+        (Command::ToggleModules, "Toggle modules pane"),
+        (Command::ToggleOutline, "Toggle outline pane"),
+        (Command::CopyImage, "Copy image"),
+        (Command::CopySvg, "Copy SVG"),
+        (Command::CopyPikchr, "Copy Pikchr"),
+        (Command::ExportSvg, "Export SVG"),
+        (Command::ExportPng(1), "Export PNG"),
+        (Command::ExportPng(2), "Export PNG (2x)"),
+        (Command::ExportPng(4), "Export PNG (4x)"),
+        (Command::ExportPikchr, "Export Pikchr"),
+    ];
+
+    pub fn message(&self) -> Option<Message> {
+        let message = match self {
+            Command::Unbind => return None,
+            Command::Undo => Message::Undo,
+            Command::Redo => Message::Redo,
+            Command::New => Message::NewRequested,
+            Command::Open => Message::LoadRequested,
+            Command::Save => Message::SaveRequested,
+            Command::SaveAs => Message::SaveAsRequested,
+            Command::RecentFiles => Message::RecentFilesRequested,
+            Command::CloseTab => Message::CloseTabRequested,
+            Command::NextTab => Message::NextTab,
+            Command::PreviousTab => Message::PreviousTab,
+            Command::SelectTab(tab) => Message::TabSelected(tab.checked_sub(1)?),
+            Command::Find => Message::Find(FindAction::Requested),
+            Command::GoToLine => Message::GoToLineRequested,
+            Command::JumpToError => Message::JumpToError,
+            Command::GoToDefinition => Message::Definition(DefinitionAction::GoTo),
+            Command::FindReferences => Message::Definition(DefinitionAction::References),
+            Command::ToggleDebug => Message::ToggleDebugOverlay,
+            Command::ToggleFileWatch => Message::ToggleFileWatch,
+            Command::ToggleModules => Message::ToggleModulesPane,
+            Command::ToggleOutline => Message::ToggleOutlinePane,
+            Command::Palette => Message::Palette(PaletteAction::Requested),
+            Command::Complete => Message::Completion(CompletionAction::Requested),
+            Command::PrologMode => Message::RadioSelected(OperatingMode::PrologMode),
+            Command::PikchrMode => Message::RadioSelected(OperatingMode::PikchrMode),
+            Command::CopyImage => Message::CopyRequested(CopyFormat::Image),
+            Command::CopySvg => Message::CopyRequested(CopyFormat::Svg),
+            Command::CopyPikchr => Message::CopyRequested(CopyFormat::Pikchr),
+            Command::ExportSvg => Message::ExportRequested(ExportFormat::Svg),
+            Command::ExportPng(scale) => Message::ExportRequested(ExportFormat::Png(*scale)),
+            Command::ExportPikchr => Message::ExportRequested(ExportFormat::Pikchr),
+            Command::Indent => Message::EditorAction(EditorAction::Indent),
+            Command::Dedent => Message::EditorAction(EditorAction::Dedent),
+            Command::NewlineIndent => Message::EditorAction(EditorAction::NewlineIndent),
+            Command::MoveUp => Message::Edit(Action::Move(Motion::Up)),
+            Command::MoveDown => Message::Edit(Action::Move(Motion::Down)),
+            Command::MoveLeft => Message::Edit(Action::Move(Motion::Left)),
+            Command::MoveRight => Message::Edit(Action::Move(Motion::Right)),
+            Command::MoveHome => Message::Edit(Action::Move(Motion::Home)),
+            Command::MoveEnd => Message::Edit(Action::Move(Motion::End)),
+            Command::MoveWordLeft => Message::Edit(Action::Move(Motion::WordLeft)),
+            Command::MoveWordRight => Message::Edit(Action::Move(Motion::WordRight)),
+            Command::SelectWordLeft => Message::Edit(Action::Select(Motion::WordLeft)),
+            Command::SelectWordRight => Message::Edit(Action::Select(Motion::WordRight)),
+            Command::DeleteWord => {
+                Message::EditBatch(vec![
+                    Action::Move(Motion::WordLeft),
+                    Action::Select(Motion::WordRight),
+                    Action::Edit(Edit::Delete),
+                ])
+            },
+            Command::DeleteLine => {
+                Message::EditBatch(vec![Action::SelectLine, Action::Edit(Edit::Delete)])
+            },
+            Command::KillLine => {
+                Message::EditBatch(vec![Action::Select(Motion::End), Action::Edit(Edit::Delete)])
+            },
+            Command::OpenLine => {
+                Message::EditBatch(vec![
+                    Action::Move(Motion::Home),
+                    Action::Edit(Edit::Insert('\n')),
+                    Action::Move(Motion::Left),
+                ])
+            },
+        };
+        Some(message)
+    }
+
+    /// Editing commands only work while the editor has focus, the rest work
+    /// anywhere and win over editing ones on the same chord.
+    pub fn is_global(&self) -> bool {
+        match self.message() {
+            // Unbound chords fall through to the editor's own handling
+            None => false,
+            Some(message) => {
+                !matches!(
+                    message,
+                    Message::Edit(_)
+                        | Message::EditBatch(_)
+                        | Message::EditorAction(_)
+                        | Message::Completion(_)
+                )
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ChordKey {
+    Named(Named),
+    /// Lowercase
+    Character(String),
+}
+
+/// Key with exact modifiers, e.g. `cmd+shift+z`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    key:       ChordKey,
+    modifiers: Modifiers,
+}
+
+impl Chord {
+    fn matches(&self, key: &Key, modifiers: Modifiers) -> bool {
+        if modifiers != self.modifiers {
+            return false;
+        }
+        match (&self.key, key.as_ref()) {
+            (ChordKey::Named(named), Key::Named(pressed)) => *named == pressed,
+            (ChordKey::Character(c), Key::Character(pressed)) => c.eq_ignore_ascii_case(pressed),
+            _ => false,
+        }
+    }
+}
+
+const NAMED_KEYS: &[(&str, Named)] = &[
+    ("tab", Named::Tab),
+    ("enter", Named::Enter),
+    ("backspace", Named::Backspace),
+    ("delete", Named::Delete),
+    ("insert", Named::Insert),
+    ("escape", Named::Escape),
+    ("space", Named::Space),
+    ("home", Named::Home),
+    ("end", Named::End),
+    ("pageup", Named::PageUp),
+    ("pagedown", Named::PageDown),
+    ("up", Named::ArrowUp),
+    ("down", Named::ArrowDown),
+    ("left", Named::ArrowLeft),
+    ("right", Named::ArrowRight),
+    ("f1", Named::F1),
+    ("f2", Named::F2),
+    ("f3", Named::F3),
+    ("f4", Named::F4),
+    ("f5", Named::F5),
+    ("f6", Named::F6),
+    ("f7", Named::F7),
+    ("f8", Named::F8),
+    ("f9", Named::F9),
+    ("f10", Named::F10),
+    ("f11", Named::F11),
+    ("f12", Named::F12),
+];
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        let mut parts: Vec<&str> = lowercase.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty());
+        let key = match key {
+            Some("plus") => ChordKey::Character(String::from("+")),
+            Some(key) if key.chars().count() == 1 => ChordKey::Character(key.to_string()),
+            Some(key) => {
+                let named = NAMED_KEYS.iter().find(|(name, _)| *name == key);
+                ChordKey::Named(named.ok_or(format!("unknown key `{}`", key))?.1)
+            },
+            None => return Err(String::from("missing key")),
+        };
+        let mut modifiers = Modifiers::empty();
+        for part in parts {
+            modifiers |= match part {
+                "cmd" | "command" => Modifiers::COMMAND,
+                "ctrl" | "control" => Modifiers::CTRL,
+                "alt" | "option" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                "super" | "logo" => Modifiers::LOGO,
+                _ => return Err(format!("unknown modifier `{}`", part)),
+            };
+        }
+        Ok(Self { key, modifiers })
+    }
+}
+
+impl Display for Chord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let logo = if cfg!(target_os = "macos") { "Cmd" } else { "Super" };
+        let modifiers = [
+            (Modifiers::CTRL, "Ctrl"),
+            (Modifiers::ALT, "Alt"),
+            (Modifiers::SHIFT, "Shift"),
+            (Modifiers::LOGO, logo),
+        ];
+        for (modifier, name) in modifiers {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match &self.key {
+            ChordKey::Named(named) => {
+                let name = NAMED_KEYS
+                    .iter()
+                    .find(|(_, key)| key == named)
+                    .map_or("?", |(name, _)| name);
+                write!(f, "{}", name)
+            },
+            // `+` separates modifiers
+            ChordKey::Character(c) if c == "+" => write!(f, "Plus"),
+            ChordKey::Character(c) => write!(f, "{}", c.to_uppercase()),
+        }
+    }
+}
+
+/// Contents of the settings file and of presets.
+#[derive(Debug, Deserialize)]
+struct Settings {
+    /// Preset the bindings are added to
+    preset:           Option<String>,
+    /// Vim-like modal editing
+    modal:            Option<bool>,
+    /// Milliseconds of typing pause before completions pop up, 0 for never
+    completion_delay: Option<u64>,
+    #[serde(default)]
+    bindings:         toml::Table,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// Several commands may share a chord, see [`Command::is_global`]
+    bindings:             Vec<(Chord, Command)>,
+    /// Keys without modifiers go to [`crate::vim`]
+    pub modal:            bool,
+    /// Milliseconds, 0 leaves completion to [`Command::Complete`]
+    pub completion_delay: u64,
+    /// Problems found in the settings file
+    pub errors:           Vec<String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self::empty();
+        keymap.add_preset(FALLBACK_PRESET);
+        keymap
+    }
+}
+
+impl Keymap {
+    fn empty() -> Self {
+        Self {
+            bindings:         vec![],
+            modal:            false,
+            completion_delay: 0,
+            errors:           vec![],
+        }
+    }
+
+    /// First chord bound to `command`, shown next to it.
+    pub fn chord(&self, command: &Command) -> Option<&Chord> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| bound == command)
+            .map(|(chord, _)| chord)
+    }
+
+    /// Reads the settings file, the fallback preset is used without one.
+    pub fn load() -> Self {
+        let Some(path) = settings_path().filter(|path| path.exists()) else {
+            return Self::default();
+        };
+        let settings = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str::<Settings>(&text).map_err(|e| e.to_string()));
+        match settings {
+            Ok(settings) => {
+                let mut keymap = Self::empty();
+                keymap.apply(settings, true);
+                keymap
+            },
+            Err(e) => {
+                let mut keymap = Self::default();
+                keymap.errors.push(format!("{}: {}", path.display(), e));
+                keymap
+            },
+        }
+    }
+
+    fn add_preset(&mut self, name: &str) {
+        match PRESETS.iter().find(|(preset, _)| *preset == name) {
+            Some((_, text)) => {
+                let settings = toml::from_str(text).expect("Presets should be valid");
+                self.apply(settings, false);
+            },
+            None => self.errors.push(format!("Unknown preset `{}`", name)),
+        }
+    }
+
+    /// Presets only add bindings, the settings file `replaces` those of
+    /// chords it binds.
+    fn apply(&mut self, settings: Settings, replaces: bool) {
+        if let Some(preset) = settings.preset {
+            self.add_preset(&preset);
+        }
+        if let Some(modal) = settings.modal {
+            self.modal = modal;
+        }
+        if let Some(delay) = settings.completion_delay {
+            self.completion_delay = delay;
+        }
+        for (chord_text, command) in settings.bindings {
+            let chord = match chord_text.parse::<Chord>() {
+                Ok(chord) => chord,
+                Err(e) => {
+                    self.errors.push(format!("Invalid chord `{}`: {}", chord_text, e));
+                    continue;
+                },
+            };
+            let command = match command.try_into::<Command>() {
+                Ok(command) => command,
+                Err(e) => {
+                    self.errors.push(format!("Invalid command for `{}`: {}", chord_text, e));
+                    continue;
+                },
+            };
+            if replaces {
+                self.bindings.retain(|(bound, _)| *bound != chord);
+            }
+            self.bindings.push((chord, command));
+        }
+    }
+
+    fn commands(&self, key: &Key, modifiers: Modifiers) -> impl Iterator<Item = &Command> {
+        self.bindings
+            .iter()
+            .filter(move |(chord, _)| chord.matches(key, modifiers))
+            .map(|(_, command)| command)
+    }
+
+    /// Command handled by the subscription, wherever the focus is.
+    pub fn global(&self, key: &Key, modifiers: Modifiers) -> Option<&Command> {
+        self.commands(key, modifiers).find(|command| command.is_global())
+    }
+
+    /// Command for the focused editor. `None` also when a global command
+    /// takes the chord, the editor leaves it alone then.
+    pub fn editor(&self, key: &Key, modifiers: Modifiers) -> Option<&Command> {
+        if self.global(key, modifiers).is_some() {
+            return None;
+        }
+        self.commands(key, modifiers).next()
+    }
+}
+
+/// Settings file, whether it exists or not.
+pub fn settings_path() -> Option<PathBuf> {
+    Some(settings_dir()?.join(SETTINGS_FILE))
+}
+
+pub fn settings_dir() -> Option<PathBuf> {
+    let project_dir = ProjectDirs::from("sh", "axk", "pikchr_pl")?;
+    Some(project_dir.config_dir().to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        for (name, _) in PRESETS {
+            let mut keymap = Keymap::empty();
+            keymap.add_preset(name);
+            assert_eq!(keymap.errors, Vec::<String>::new(), "preset {}", name);
+            assert!(!keymap.bindings.is_empty(), "preset {}", name);
+        }
+    }
+
+    #[test]
+    fn chords_round_trip() {
+        for text in [
+            "a",
+            "ctrl+shift+z",
+            "alt+f2",
+            "super+tab",
+            "ctrl+plus",
+            "shift+pagedown",
+            "ctrl+alt+shift+super+space",
+        ] {
+            let chord: Chord = text.parse().unwrap();
+            let shown = chord.to_string();
+            assert_eq!(shown.parse::<Chord>(), Ok(chord), "{} shown as {}", text, shown);
+        }
+    }
+
+    #[test]
+    fn chords_show_modifiers_in_order() {
+        let chord: Chord = "shift+ctrl+k".parse().unwrap();
+        assert_eq!(chord.to_string(), "Ctrl+Shift+K");
+        let chord: Chord = "alt+enter".parse().unwrap();
+        assert_eq!(chord.to_string(), "Alt+enter");
+    }
+
+    #[test]
+    fn invalid_chords_are_rejected() {
+        assert_eq!("ctrl+".parse::<Chord>(), Err(String::from("missing key")));
+        assert_eq!(
+            "hyper+a".parse::<Chord>(),
+            Err(String::from("unknown modifier `hyper`"))
+        );
+        assert_eq!(
+            "ctrl+return".parse::<Chord>(),
+            Err(String::from("unknown key `return`"))
+        );
+    }
+}
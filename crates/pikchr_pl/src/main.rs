@@ -11,41 +11,66 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{fmt::Display, path::PathBuf, time::Duration};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use iced::{
-    Alignment, Color, Element, Length, Task, Theme,
+    Alignment, Color, Element, Font, Length, Task, Theme,
     widget::{
-        button, column, container, pane_grid, pick_list, row, space, stack, svg, text::Shaping,
-        text_editor::Content,
+        button, canvas, column, container, mouse_area, pane_grid, pick_list, responsive, rich_text,
+        row, scrollable, space, span, stack, svg,
+        text::{Highlighter, Shaping, Span},
+        text_editor::{Cursor, Position},
     },
     window::icon,
 };
 use pikchr_pro::{
     pikchr::{self, PikchrCode},
-    prolog::engine::trealla::EngineAsync as PrologEngine,
+    prolog::{
+        RenderError,
+        engine::trealla::{CancellationToken, EngineAsync as PrologEngine},
+    },
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::watch;
 
+mod clipboard;
+mod completion;
 mod constants;
+mod definition;
+mod document;
 mod editor_actions_handler;
 mod editor_state;
+mod export;
 mod file_watcher;
+mod find;
+mod gutter;
 mod keybindings;
 mod messages;
+mod module_panel;
+mod outline;
+mod palette;
+mod preview;
+mod recent_files;
 mod prolog_modules;
+mod prolog_source;
 mod string_ext;
 mod save_state;
+mod source_map;
 mod heredoc_parser;
 mod text_highlighting;
 mod undo;
+mod vim;
 
+use document::{Document, DocumentId};
 use editor_state::Editor;
 use messages::Message;
 
-use crate::{editor_state::NEW_CONTENT, heredoc_parser::transform_heredoc, prolog_modules::PrologModules, save_state::Stateful, string_ext::StringExt, text_highlighting::PrologHighlighter, undo::UndoStack};
+use crate::{clipboard::CopyFormat, editor_state::NEW_CONTENT, export::{ExportError, ExportFormat}, heredoc_parser::transform_heredoc, keybindings::keymap::{self, Keymap}, prolog_modules::Program, save_state::Stateful, text_highlighting::{PikchrHighlighter, PikchrToken, PrologHighlighter}};
 
 const DEBOUNCE_MS: u64 = 100;
 
@@ -69,9 +94,23 @@ enum OperatingMode {
     PrologMode,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum PaneContent {
     Editor,
     Preview,
+    Modules,
+    Outline,
+}
+
+impl OperatingMode {
+    /// Mode matching the extension used by file dialogs.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "pik" => Some(OperatingMode::PikchrMode),
+            "pl" => Some(OperatingMode::PrologMode),
+            _ => None,
+        }
+    }
 }
 
 impl Display for OperatingMode {
@@ -86,35 +125,135 @@ impl Display for OperatingMode {
 impl Editor {
     fn new() -> (Self, Task<Message>) {
         let mut editor = Editor::default();
-        let load_result = editor.state_load();
-        let mut messages: Vec<Task<Message>> = Vec::new();
-        if load_result.is_ok() && editor.current_file.is_some() {
-            let current_file = editor.current_file.clone();
-            messages.push(Task::done(Message::LoadFileSelected(current_file)))
-        } else {
-            messages.push(Task::done(Message::RunLogic));
+        // Reopens tabs, starting with a blank one is fine if that fails
+        let _ = editor.state_load();
+        editor.reload_modules();
+        let mut messages = vec![
+            Task::done(Message::RunLogic),
+            iced::widget::operation::focus("editor"),
+            iced::system::theme().map(Message::ThemeModeChanged),
+        ];
+        // Files passed on command line, e.g. by file manager
+        for path in std::env::args_os().skip(1).map(PathBuf::from) {
+            let path = std::fs::canonicalize(&path).unwrap_or(path);
+            messages.push(Task::done(Message::LoadFileSelected(Some(path))));
         }
-        messages.push(
-            iced::widget::operation::focus("editor")
-        );
         (editor, Task::batch(messages))
 
     }
     fn set_title(&self) -> String {
-        let file: String = self
+        let document = self.doc();
+        let file: String = document
             .current_file
             .clone()
             .map(|p| p.to_string_lossy().into_owned())
             .unwrap_or(String::from("Unnamed"));
-        let dirty = if self.dirty { "*" } else { "" };
+        let dirty = if document.dirty { "*" } else { "" };
         format!("Pikchr.pl - {}{}", file, dirty)
     }
-    fn reset_editor(&mut self) -> Task<Message> {
-        *self = Self::default();
-        self.content = Content::with_text(NEW_CONTENT);
+    fn new_document(&mut self) -> Task<Message> {
+        let id = self.next_document_id();
+        let mode = self.doc().operating_mode;
+        self.push_document(Document::new(id, NEW_CONTENT, mode));
+        self.load_modules(self.active);
+        self.switched_document()
+    }
+    fn select_tab(&mut self, index: usize) -> Task<Message> {
+        if index >= self.documents.len() || index == self.active {
+            return Task::none();
+        }
+        self.active = index;
+        self.switched_document()
+    }
+    /// Writes the document to its file, asks for one if it has none yet.
+    fn save_document(&self, id: DocumentId) -> Task<Message> {
+        let Some(index) = self.document_index(id) else {
+            return Task::none();
+        };
+        match self.documents[index].current_file.clone() {
+            Some(path) => Task::done(Message::SaveFileSelected(id, Some(path))),
+            None => self.save_document_as(id),
+        }
+    }
+    fn save_document_as(&self, id: DocumentId) -> Task<Message> {
+        let Some(index) = self.document_index(id) else {
+            return Task::none();
+        };
+        let document = &self.documents[index];
+        let mode = document.operating_mode;
+        let current_file_opt = document
+            .current_file
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|n| n.to_string_lossy().into_owned());
+
+        Task::perform(
+            async move {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .set_title("Save File")
+                    .add_filter_according_to_mode(mode);
+
+                if let Some(basename) = current_file_opt {
+                    dialog.set_file_name(basename)
+                } else {
+                    dialog
+                }
+                .save_file()
+                .await
+                .map(|handle| handle.path().to_path_buf())
+            },
+            move |path| Message::SaveFileSelected(id, path),
+        )
+    }
+    /// Closes the tab at `index`, asking whether to save unsaved changes
+    /// first.
+    fn close_tab(&mut self, index: usize) -> Task<Message> {
+        let Some(document) = self.documents.get(index) else {
+            return Task::none();
+        };
+        if !document.dirty || document.is_blank() {
+            self.close_document(index);
+            return self.switched_document();
+        }
+        let id = document.id;
+        let description = format!("Save changes to {} before closing?", document.name());
+        Task::perform(
+            async move {
+                rfd::AsyncMessageDialog::new()
+                    .set_title("Close Tab")
+                    .set_description(description)
+                    .set_buttons(rfd::MessageButtons::YesNoCancel)
+                    .show()
+                    .await
+            },
+            move |answer| match answer {
+                rfd::MessageDialogResult::Yes => Message::SaveAndCloseRequested(id),
+                rfd::MessageDialogResult::No => Message::DiscardAndCloseRequested(id),
+                _ => Message::Nothing(()),
+            },
+        )
+    }
+    /// Closes the tab of a document without asking.
+    fn close_tab_of(&mut self, id: DocumentId) -> Task<Message> {
+        match self.document_index(id) {
+            Some(index) => {
+                self.close_document(index);
+                self.switched_document()
+            },
+            None => Task::none(),
+        }
+    }
+    /// Brings editor and preview up to date with a newly active tab.
+    fn switched_document(&mut self) -> Task<Message> {
+        self.editor_viewport = None;
+        self.completion.close();
         Task::batch([
             iced::widget::operation::focus("editor"),
-            Task::done(Message::RunLogic)
+            Task::done(Message::RunLogic),
+            iced::widget::operation::scroll_to(
+                gutter::SCROLL_ID,
+                iced::widget::operation::AbsoluteOffset { x: None, y: Some(0.0) },
+            ),
         ])
     }
     fn update(&mut self, message: Message) -> Task<Message> {
@@ -122,26 +261,37 @@ impl Editor {
         // Message Matching Logic
         match message {
             LoadFileSelected(Some(path_buf)) => {
-                self.current_file = Some(path_buf);
-                let path_ref: &PathBuf = self.current_file.as_ref().unwrap();
-
-                if let Ok(file_as_string) = std::fs::read_to_string(path_ref) {
-                    self.content = Content::with_text(&file_as_string);
-                    self.undo_stack = UndoStack::new(self.content.clone());
-                    self.dirty = false;
-                    Task::batch([
-                        Task::done(SaveTick),
-                        Task::done(Message::RunLogic),
-                    ])
-                } else {
-                    Task::done(Message::ShowError(ApplicationError::FileLoadFailure(
-                        path_ref.clone(),
-                    )))
+                self.show_recent_files = false;
+                let open = self
+                    .documents
+                    .iter()
+                    .position(|document| document.current_file.as_ref() == Some(&path_buf));
+                if let Some(index) = open {
+                    return self.select_tab(index);
+                }
+
+                let id = self.next_document_id();
+                let mode = OperatingMode::from_path(&path_buf).unwrap_or(self.doc().operating_mode);
+                match Document::open(id, &path_buf, mode) {
+                    Ok(document) => {
+                        recent_files::remember(&mut self.recent_files, &path_buf);
+                        self.push_document(document);
+                        self.load_modules(self.active);
+                        Task::batch([Task::done(SaveTick), self.switched_document()])
+                    },
+                    Err(e) => {
+                        recent_files::forget(&mut self.recent_files, &path_buf);
+                        Task::done(Message::ShowError(e))
+                    },
                 }
             },
             LoadFileSelected(_) => Task::none(),
+            RecentFilesRequested => {
+                self.show_recent_files = !self.show_recent_files;
+                Task::none()
+            },
             LoadRequested => {
-                let mode = self.operating_mode;
+                let mode = self.doc().operating_mode;
                 Task::perform(
                     async move {
                         rfd::AsyncFileDialog::new()
@@ -158,17 +308,101 @@ impl Editor {
                 self.modifiers = modifiers;
                 Task::none()
             },
-            NewRequested => self.reset_editor(),
+            KeyPressed(key, modifiers) => {
+                if self.palette.is_some()
+                    && let Some(action) = palette::navigation(&key)
+                {
+                    return palette::handle(self, action);
+                }
+                match self
+                    .keymap
+                    .global(&key, modifiers)
+                    .and_then(|command| command.message())
+                {
+                    Some(message) => Task::done(message),
+                    None => Task::none(),
+                }
+            },
+            SettingsChanged => {
+                self.keymap = Keymap::load();
+                if !self.keymap.modal {
+                    self.vim = vim::Vim::default();
+                }
+                Task::none()
+            },
+            NewRequested => self.new_document(),
             Edit(action @ iced::widget::text_editor::Action::Edit(_)) => {
-                self.undo_stack.push(&self.content);
-                self.dirty = true;
-                self.content.perform(action);
-                Task::done(RunLogic)
+                let document = self.doc_mut();
+                document.undo_stack.push(&document.content);
+                document.dirty = true;
+                document.content.perform(action.clone());
+                let completion = completion::edited(self, &action);
+                Task::batch([Task::done(RunLogic), self.scroll_to_cursor(), completion])
             },
             Edit(action) => {
-                self.content.perform(action);
+                // Cursor left the word being completed
+                self.completion.close();
+                let definition = matches!(action, iced::widget::text_editor::Action::Click(_))
+                    && self.modifiers.command();
+                self.doc_mut().content.perform(action);
+                if definition {
+                    return Task::done(Definition(messages::DefinitionAction::GoTo));
+                }
+                self.scroll_to_cursor()
+            },
+            Dismiss => {
+                let goto_line = self.goto_line.take();
+                let find = self.find.take();
+                let recent_files = std::mem::take(&mut self.show_recent_files);
+                let command_line = self.vim.command_line.take();
+                let palette = self.palette.take();
+                let completion = self.completion.popup.take();
+                let references = self.references.take();
+                if goto_line.is_some()
+                    || find.is_some()
+                    || recent_files
+                    || command_line.is_some()
+                    || palette.is_some()
+                    || completion.is_some()
+                    || references.is_some()
+                {
+                    iced::widget::operation::focus("editor")
+                } else {
+                    Task::none()
+                }
+            },
+            EditorScrolled(viewport) => {
+                self.editor_viewport = Some(viewport);
                 Task::none()
             },
+            CopyRequested(CopyFormat::Image) => {
+                let Some(svg) = self.doc().svg_source.clone() else {
+                    return Task::done(ShowError(ExportError::NothingToExport.into()));
+                };
+                if self.clipboard.is_none() {
+                    self.clipboard = arboard::Clipboard::new().ok();
+                }
+                let result = match self.clipboard.as_mut() {
+                    Some(board) => clipboard::copy_image(board, &svg),
+                    None => Err(ExportError::ClipboardFailure(String::from(
+                        "can't access system clipboard",
+                    ))),
+                };
+                match result {
+                    Ok(_) => Task::none(),
+                    Err(e) => Task::done(ShowError(e.into())),
+                }
+            },
+            CopyRequested(format) => {
+                let text = match format {
+                    CopyFormat::Svg => self.doc().svg_source.clone(),
+                    _ => self.doc().current_pikchr(),
+                };
+                match text {
+                    Some(text) => iced::clipboard::write(text),
+                    None => Task::done(ShowError(ExportError::NothingToExport.into())),
+                }
+            },
             EditBatch(actions) => Task::batch(
                 actions
                     .into_iter()
@@ -176,172 +410,462 @@ impl Editor {
                     .map(Task::done)
                     .collect::<Vec<_>>(),
             ),
-            PikchrFinished(result) => {
+            ExportRequested(format) => {
+                let file_name = format!(
+                    "{}.{}",
+                    self.doc()
+                        .current_file
+                        .as_ref()
+                        .and_then(|path| path.file_stem())
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or(String::from("diagram")),
+                    format.extension()
+                );
+
+                Task::perform(
+                    async move {
+                        rfd::AsyncFileDialog::new()
+                            .set_title(format!("Export {}", format))
+                            .add_filter(format.to_string(), &[format.extension()])
+                            .set_file_name(file_name)
+                            .save_file()
+                            .await
+                            .map(|handle| handle.path().to_path_buf())
+                    },
+                    move |path| Message::ExportFileSelected(format, path),
+                )
+            },
+            ExportFileSelected(format, Some(path_buf)) => {
+                let document = self.doc();
+                let pikchr = document.current_pikchr();
+                match export::export(
+                    &path_buf,
+                    format,
+                    document.svg_source.as_deref(),
+                    pikchr.as_deref(),
+                ) {
+                    Ok(_) => Task::none(),
+                    Err(e) => Task::done(ShowError(e.into())),
+                }
+            },
+            ExportFileSelected(_, None) => Task::none(),
+            PikchrFinished(id, result) => {
                 self.is_compiling = false;
+                let Some(document) = self.document_mut(id) else {
+                    // Tab was closed meanwhile
+                    return Task::none();
+                };
                 match result {
                     // Stale result
-                    None => Task::none(),
+                    None => (),
                     Some(Ok(string)) => {
+                        document.svg_size = preview::natural_size(&string);
+                        document.svg_source = Some(string.clone());
                         let bytes = string.into_bytes();
                         let handle = svg::Handle::from_memory(bytes);
-                        self.last_successful = true;
-                        self.last_error.set(String::new());
-                        self.svg_handle = Some(handle);
-                        Task::none()
+                        document.last_successful = true;
+                        document.last_error.set(String::new());
+                        document.error_line = None;
+                        document.svg_handle = Some(handle);
                     },
                     Some(Err(e)) => {
-                        self.last_successful = false;
-                        Task::done(Message::ShowError(e))
+                        document.last_successful = false;
+                        document.show_error(e);
                     },
                 }
+                Task::none()
             },
-            PrologFinished(result) => match result {
-                Some(Ok(input)) => {
-                    self.pikchr_code = Some(input.clone());
-                    Task::done(Message::RunPikchr(input))
-                },
-                Some(Err(err)) => Task::done(Message::ShowError(err)),
-                None => Task::none(),
+            PreviewChanged(view) => {
+                self.doc_mut().preview_view = view;
+                Task::none()
+            },
+            PreviewClicked(point) => {
+                let document = self.doc_mut();
+                let Some(line) = document
+                    .svg_source
+                    .as_deref()
+                    .and_then(|svg| preview::object_at(svg, point))
+                else {
+                    return Task::none();
+                };
+                document.selected_pikchr_line = Some(line);
+
+                let source_line = match document.operating_mode {
+                    OperatingMode::PikchrMode => Some(line),
+                    OperatingMode::PrologMode => document
+                        .pikchr_code
+                        .as_ref()
+                        .and_then(|code| code.as_str().lines().nth(line))
+                        .and_then(|pikchr_line| {
+                            source_map::prolog_line(pikchr_line, &document.content.text())
+                        }),
+                };
+                match source_line {
+                    Some(source_line) => {
+                        self.select_line(source_line);
+                        Task::batch([
+                            iced::widget::operation::focus("editor"),
+                            self.scroll_to_cursor(),
+                        ])
+                    },
+                    None => Task::none(),
+                }
+            },
+            PrologFinished(id, result) => {
+                let Some(document) = self.document_mut(id) else {
+                    return Task::none();
+                };
+                match result {
+                    Some(Ok(input)) => {
+                        document.selected_pikchr_line = None;
+                        document.pikchr_code = Some(input.clone());
+                        Task::done(Message::RunPikchr(id, input))
+                    },
+                    Some(Err(err)) => {
+                        document.show_error(err);
+                        Task::none()
+                    },
+                    None => Task::none(),
+                }
             },
             RadioSelected(operating_mode) => {
-                self.operating_mode = operating_mode;
+                self.doc_mut().operating_mode = operating_mode;
                 Task::done(Message::RunLogic)
             },
             RefreshTick => {
-                self.last_error.commit();
+                for document in &mut self.documents {
+                    document.last_error.commit();
+                }
                 Task::none()
             },
             SaveTick => {
                 let save_data = self.to_save_state();
                 Task::perform(
-                    async { Editor::save_write(save_data); },
+                    async { let _ = Editor::save_write(save_data); },
                     Message::Nothing
                 )
             }
             RunLogic => {
-                let input = self.content.text();
-                match self.operating_mode {
-                    OperatingMode::PikchrMode => Task::done(Message::RunPikchr(input.into())),
-                    OperatingMode::PrologMode => Task::done(Message::RunProlog(input)),
+                let document = self.doc();
+                let input = document.content.text();
+                match document.operating_mode {
+                    OperatingMode::PikchrMode => {
+                        Task::done(Message::RunPikchr(document.id, input.into()))
+                    },
+                    OperatingMode::PrologMode => Task::done(Message::RunProlog(document.id, input)),
                 }
             },
-            RunPikchr(input) => {
-                let input_rx = self.pikchr_input_rx.clone();
-                let _ = self.pikchr_input_tx.send(input);
-                Task::perform(
-                    render_pikchr(self.last_successful, input_rx),
-                    Message::PikchrFinished,
-                )
+            RunPikchr(id, input) => {
+                let Some(document) = self.document_mut(id) else {
+                    return Task::none();
+                };
+                let last_successful = document.last_successful;
+                let input_rx = document.pikchr_input_rx.clone();
+                let _ = document.pikchr_input_tx.send(input);
+                Task::perform(render_pikchr(last_successful, input_rx), move |result| {
+                    Message::PikchrFinished(id, result)
+                })
             },
-            RunProlog(input) => {
-                let input_rx = self.prolog_input_rx.clone();
-                let _ = self.prolog_input_tx.send(input);
-                let modules = self.modules.clone();
+            RunProlog(id, input) => {
+                let Some(document) = self.document_mut(id) else {
+                    return Task::none();
+                };
+                let last_successful = document.last_successful;
+                let program = document.modules.link(&input);
+                document.warnings = program.warnings.clone();
+                let input_rx = document.prolog_input_rx.clone();
+                let _ = document.prolog_input_tx.send(program);
+
+                // Anything still running for this tab is stale now
+                document.prolog_cancel.cancel();
+                document.prolog_cancel = CancellationToken::new();
+                let cancel = document.prolog_cancel.clone();
 
                 Task::perform(
-                    render_diagram(self.last_successful, input_rx, modules),
-                    Message::PrologFinished,
+                    render_diagram(last_successful, input_rx, cancel),
+                    move |result| Message::PrologFinished(id, result),
                 )
             },
-            SaveFileSelected(path_buf_opt) => {
-                self.current_file = path_buf_opt.clone();
-                if let Some(path_buf) = path_buf_opt {
-                    match std::fs::write(path_buf, self.content.text()) {
-                        Ok(_) => (),
-                        Err(_) => return Task::done(ShowError(ApplicationError::SaveFailure)),
-                    };
+            SaveFileSelected(id, Some(path_buf)) => {
+                // Tab closed while the dialog was open
+                let Some(index) = self.document_index(id) else {
+                    return Task::none();
+                };
+                let document = &mut self.documents[index];
+                if std::fs::write(&path_buf, document.content.text()).is_err() {
+                    document.close_when_saved = false;
+                    return Task::done(ShowError(ApplicationError::SaveFailure));
                 }
-                Task::done(Message::SaveFinished)
+                document.current_file = Some(path_buf.clone());
+                recent_files::remember(&mut self.recent_files, &path_buf);
+                self.remember_module_selection(index);
+                // Project modules come from next to the file
+                self.load_modules(index);
+                Task::done(Message::SaveFinished(id))
             },
-            SaveFinished => {
-                self.dirty = false;
+            SaveFileSelected(id, None) => {
+                if let Some(document) = self.document_mut(id) {
+                    document.close_when_saved = false;
+                }
                 Task::none()
             },
-            SaveRequested => {
-                if self.current_file.is_none() {
-                    Task::done(SaveAsRequested)
+            SaveFinished(id) => {
+                let Some(document) = self.document_mut(id) else {
+                    return Task::none();
+                };
+                document.dirty = false;
+                if document.close_when_saved {
+                    self.close_tab_of(id)
                 } else {
-                    Task::done(SaveFileSelected(self.current_file.clone()))
+                    Task::none()
                 }
             },
-            SaveAsRequested => {
-                let mode = self.operating_mode;
-                let current_file_opt = self
-                    .current_file
-                    .as_ref()
-                    .and_then(|path| path.file_name())
-                    .map(|n| n.to_string_lossy().into_owned());
-
-                Task::perform(
-                    async move {
-                        let dialog = rfd::AsyncFileDialog::new()
-                            .set_title("Save File")
-                            .add_filter_according_to_mode(mode);
-
-                        if let Some(basename) = current_file_opt {
-                            dialog.set_file_name(basename)
-                        } else {
-                            dialog
-                        }
-                        .save_file()
-                        .await
-                        .map(|handle| handle.path().to_path_buf())
-                    },
-                    Message::SaveFileSelected,
-                )
+            SaveRequested => self.save_document(self.doc().id),
+            SaveAsRequested => self.save_document_as(self.doc().id),
+            GoToLineInput(input) => {
+                self.goto_line = Some(input.chars().filter(char::is_ascii_digit).collect());
+                Task::none()
+            },
+            GoToLineRequested => {
+                self.goto_line = Some(String::new());
+                iced::widget::operation::focus("goto_line")
+            },
+            GoToLineSubmitted => {
+                let Some(line) = self.goto_line.take().and_then(|input| input.parse::<usize>().ok())
+                else {
+                    return iced::widget::operation::focus("editor");
+                };
+                let content = &mut self.doc_mut().content;
+                let line = line.clamp(1, content.line_count()) - 1;
+                content.move_to(Cursor {
+                    position:  Position { line, column: 0 },
+                    selection: None,
+                });
+                Task::batch([
+                    iced::widget::operation::focus("editor"),
+                    self.scroll_to_cursor(),
+                ])
+            },
+            JumpToError => {
+                let Some(line) = self.doc().error_line else {
+                    return Task::none();
+                };
+                self.doc_mut().content.move_to(Cursor {
+                    position:  Position { line, column: 0 },
+                    selection: None,
+                });
+                Task::batch([
+                    iced::widget::operation::focus("editor"),
+                    self.scroll_to_cursor(),
+                ])
             },
             ShowError(error) => {
-                match error {
-                    ApplicationError::PikchrPrologError(render_error) => {
-                        self.last_error.set(format!("{:?}", render_error));
-                    },
-                    ApplicationError::PikchrError(render_error) => {
-                        let trimmed = render_error.trim_last_chars(500).trim_last_lines(5);
-
-                        self.last_error.set(trimmed);
-                    },
-                    ApplicationError::PikchrEmpty => (),
-                    ApplicationError::Unknown => self.last_error.set(String::from("Unknown error")),
-                    ApplicationError::FileLoadFailure(path_buf) => {
-                        self.last_error.set(format!(
-                            "Failed to load file: {}",
-                            path_buf.to_string_lossy()
-                        ));
-                    },
-                    ApplicationError::SaveFailure => self.last_error.set(error.to_string()),
-                }
+                self.doc_mut().show_error(error);
                 Task::none()
             },
             ToggleDebugOverlay => {
                 self.show_debug = !self.show_debug;
                 Task::none()
             },
+            ToggleModulesPane => {
+                self.toggle_side_pane(PaneContent::Modules);
+                Task::none()
+            },
+            ToggleOutlinePane => {
+                self.toggle_side_pane(PaneContent::Outline);
+                Task::none()
+            },
+            OutlineGroupToggled(indicator) => {
+                if !self.outline_collapsed.remove(&indicator) {
+                    self.outline_collapsed.insert(indicator);
+                }
+                Task::none()
+            },
+            JumpToLine(line) => {
+                Task::batch([iced::widget::operation::focus("editor"), self.jump_to_line(line)])
+            },
+            ModuleToggled(module, enabled) => {
+                self.doc_mut().modules.set_enabled(&module, enabled);
+                self.remember_module_selection(self.active);
+                Task::done(Message::RunLogic)
+            },
+            ModuleViewed(module) => {
+                self.viewed_module = (self.viewed_module.as_ref() != Some(&module)).then_some(module);
+                self.viewed_module_line = None;
+                Task::none()
+            },
+            ModulesChanged => {
+                self.reload_modules();
+                Task::done(Message::RunLogic)
+            },
+            UserModulesDirRequested => Task::perform(
+                async {
+                    rfd::AsyncFileDialog::new()
+                        .set_title("User Modules Folder")
+                        .pick_folder()
+                        .await
+                        .map(|handle| handle.path().to_path_buf())
+                },
+                Message::UserModulesDirSelected,
+            ),
+            UserModulesDirSelected(Some(path_buf)) => {
+                self.configured_modules_dir = Some(path_buf);
+                self.reload_modules();
+                Task::done(Message::RunLogic)
+            },
+            UserModulesDirSelected(None) => Task::none(),
             ToggleFileWatch => {
                 self.file_watch_mode = !self.file_watch_mode;
                 Task::none()
             },
             Undo => {
-                self.undo_stack.undo_into(&mut self.content);
-                Task::none()
+                let document = self.doc_mut();
+                document.undo_stack.undo_into(&mut document.content);
+                self.scroll_to_cursor()
             },
             Redo => {
-                self.undo_stack.redo_into(&mut self.content);
-                Task::none()
+                let document = self.doc_mut();
+                document.undo_stack.redo_into(&mut document.content);
+                self.scroll_to_cursor()
             },
             PaneResized(pane_grid::ResizeEvent { split, ratio }) => {
                 self.panes.resize(split, ratio);
                 Task::none()
             },
-            EditorAction(msg) => editor_actions_handler::handle(self, msg),
-            LoadedFileChanged => Task::done(Message::LoadFileSelected(self.current_file.clone())),
+            EditorAction(msg) => {
+                let task = editor_actions_handler::handle(self, msg);
+                Task::batch([task, self.scroll_to_cursor()])
+            },
+            Find(action) => find::handle(self, action),
+            Vim(action) => vim::handle(self, action),
+            Palette(action) => palette::handle(self, action),
+            Completion(action) => completion::handle(self, action),
+            Definition(action) => definition::handle(self, action),
+            LoadedFileChanged => match self.doc_mut().reload() {
+                Ok(()) => Task::done(Message::RunLogic),
+                Err(e) => Task::done(Message::ShowError(e)),
+            },
+            TabSelected(index) => self.select_tab(index),
+            NextTab => self.select_tab((self.active + 1) % self.documents.len()),
+            PreviousTab => {
+                let count = self.documents.len();
+                self.select_tab((self.active + count - 1) % count)
+            },
+            TabClosed(index) => self.close_tab(index),
+            CloseTabRequested => self.close_tab(self.active),
+            SaveAndCloseRequested(id) => {
+                let Some(document) = self.document_mut(id) else {
+                    return Task::none();
+                };
+                document.close_when_saved = true;
+                self.save_document(id)
+            },
+            DiscardAndCloseRequested(id) => self.close_tab_of(id),
+            ThemeModeChanged(mode) => {
+                self.theme_mode = mode;
+                Task::none()
+            },
             Nothing(_) => Task::none(),
         }
     }
+    /// Moves cursor to the end of `line`, selecting it whole.
+    fn select_line(&mut self, line: usize) {
+        let content = &mut self.doc_mut().content;
+        let Some(text) = content.line(line) else {
+            return;
+        };
+        let end = text.text.len();
+        content.move_to(Cursor {
+            position:  Position { line, column: end },
+            selection: Some(Position { line, column: 0 }),
+        });
+    }
+    fn pane_of(&self, content: PaneContent) -> Option<pane_grid::Pane> {
+        self.panes
+            .iter()
+            .find(|(_, shown)| **shown == content)
+            .map(|(pane, _)| *pane)
+    }
+    /// Opens `content` left of the editor, or closes it if it's open.
+    fn toggle_side_pane(&mut self, content: PaneContent) {
+        match self.pane_of(content) {
+            Some(pane) => {
+                self.panes.close(pane);
+            },
+            None => self.open_side_pane(content),
+        }
+    }
+    /// Opens `content` left of the editor unless it's open already.
+    fn open_side_pane(&mut self, content: PaneContent) {
+        if self.pane_of(content).is_some() {
+            return;
+        }
+        let Some(editor_pane) = self.pane_of(PaneContent::Editor) else {
+            return;
+        };
+        if let Some((pane, split)) =
+            self.panes.split(pane_grid::Axis::Vertical, editor_pane, content)
+        {
+            // Keep it on the left as a side panel
+            self.panes.swap(pane, editor_pane);
+            self.panes.resize(split, 0.35);
+        }
+    }
+    /// Settings problems stay on top until fixed.
+    fn info(&self) -> String {
+        let info = self.doc().info();
+        if self.keymap.errors.is_empty() {
+            return info;
+        }
+        format!("{}\n{}", self.keymap.errors.join("\n"), info)
+    }
+    /// Puts the cursor at the start of 0-based `line`.
+    fn jump_to_line(&mut self, line: usize) -> Task<Message> {
+        let content = &mut self.doc_mut().content;
+        let line = line.min(content.line_count().saturating_sub(1));
+        content.move_to(Cursor {
+            position:  Position { line, column: 0 },
+            selection: None,
+        });
+        self.scroll_to_cursor()
+    }
+    fn scroll_to_cursor(&self) -> Task<Message> {
+        let content = &self.doc().content;
+        let position = content.cursor().position;
+        let column_chars = content
+            .line(position.line)
+            .and_then(|line| line.text.get(..position.column).map(|text| text.chars().count()))
+            .unwrap_or(0);
+        gutter::scroll_to_cursor(
+            self.editor_viewport,
+            content.line_count(),
+            position.line,
+            column_chars,
+        )
+    }
     fn view(&self) -> Element<'_, Message> {
         let panes = pane_grid(&self.panes, |_pane, content, _is_focused| {
             let content_widget = match content {
-                PaneContent::Editor => self.input_pane(self.operating_mode),
+                PaneContent::Editor => self.input_pane(self.doc().operating_mode),
                 PaneContent::Preview => self.preview_pane(),
+                PaneContent::Modules => {
+                    module_panel::view(
+                        &self.doc().modules,
+                        self.viewed_module.as_deref(),
+                        self.viewed_module_line,
+                        self.user_modules_dir(),
+                    )
+                },
+                PaneContent::Outline => {
+                    let document = self.doc();
+                    let source = (document.operating_mode == OperatingMode::PrologMode)
+                        .then(|| document.content.text());
+                    outline::view(
+                        source.as_deref(),
+                        &self.outline_collapsed,
+                        document.content.cursor().position.line,
+                    )
+                },
             };
             pane_grid::Content::new(content_widget)
         })
@@ -350,7 +874,7 @@ impl Editor {
         .width(Length::Fill)
         .height(Length::Fill);
         let info_box = container(
-            iced::widget::text(self.last_error.get())
+            iced::widget::text(self.info())
                 .font(iced::font::Font::MONOSPACE)
                 .shaping(Shaping::Basic)
                 .width(Length::Fill)
@@ -358,6 +882,13 @@ impl Editor {
         )
         .height(Length::Fixed(75.0))
         .padding(10);
+        let info_box = mouse_area(info_box)
+            .on_press(Message::JumpToError)
+            .interaction(if self.doc().error_line.is_some() {
+                iced::mouse::Interaction::Pointer
+            } else {
+                iced::mouse::Interaction::default()
+            });
 
         let main_pane = if self.file_watch_mode {
             self.preview_pane()
@@ -370,7 +901,7 @@ impl Editor {
         } else {
             stack![main_pane]
         };
-        column![self.menu_bar(), content, row![info_box]]
+        column![self.menu_bar(), self.tab_bar(), content, row![info_box]]
             .spacing(10)
             .padding(10)
             .into()
@@ -381,37 +912,143 @@ impl Editor {
             iced::time::every(Duration::from_millis(500)).map(|_| Message::RefreshTick),
             iced::time::every(Duration::from_secs(5)).map(|_| Message::SaveTick),
             keybindings::listen(),
+            iced::system::theme_changes().map(Message::ThemeModeChanged),
         ];
-        if let (true, Some(file)) = (self.file_watch_mode, &self.current_file) {
+        if let (true, Some(file)) = (self.file_watch_mode, &self.doc().current_file) {
             subscriptions.push(file_watcher::file_watcher(file))
         }
+        for dir in self.module_dirs() {
+            subscriptions.push(file_watcher::module_dir_watcher(&dir))
+        }
+        if let Some(dir) = keymap::settings_dir().filter(|dir| dir.is_dir()) {
+            subscriptions.push(file_watcher::settings_watcher(&dir))
+        }
         iced::Subscription::batch(subscriptions)
     }
 
-    fn input_pane(&self, mode: OperatingMode) -> Element<'_, Message> {
-        let editor = iced::widget::text_editor(&self.content)
+    /// Editor as wide as `width`, highlighted for `mode`.
+    fn code_editor(&self, mode: OperatingMode, width: f32) -> Element<'_, Message> {
+        let document = self.doc();
+        let keymap = &self.keymap;
+        let completing = self.completion.popup.is_some();
+        let editor = iced::widget::text_editor(&document.content)
             .on_action(Message::Edit)
             .id("editor")
-            .key_binding(keybindings::handle_action)
-            .height(Length::Fill)
-            .size(12)
+            .key_binding(move |keypress| keybindings::handle_action(keymap, completing, keypress))
+            // Grows with content, scrolling is left to the scrollable around
+            .width(width)
+            .height(Length::Shrink)
+            .wrapping(iced::widget::text::Wrapping::None)
+            .size(gutter::TEXT_SIZE)
+            .padding(gutter::PADDING)
             .font(iced::font::Font::MONOSPACE);
 
-				if mode == OperatingMode::PrologMode {
-    				editor
-        				.highlight_with::<PrologHighlighter>((), PrologHighlighter::colorize)
-        				.into()
-				} else {
-    				editor.into()
-				}
+        match mode {
+            OperatingMode::PrologMode => {
+                editor
+                    .highlight_with::<PrologHighlighter>(
+                        document.error_line,
+                        PrologHighlighter::colorize,
+                    )
+                    .into()
+            },
+            OperatingMode::PikchrMode => {
+                editor
+                    .highlight_with::<PikchrHighlighter>(
+                        document.error_line,
+                        PikchrHighlighter::colorize,
+                    )
+                    .into()
+            },
+        }
+    }
+
+    fn input_pane(&self, mode: OperatingMode) -> Element<'_, Message> {
+        let document = self.doc();
+        let line_count = document.content.line_count();
+        let content_width = gutter::content_width(&document.content);
 
+        let editor = responsive(move |size| {
+            // Fills the pane, lines that don't fit scroll sideways
+            let width = content_width.max(size.width - gutter::width(line_count) - gutter::SPACING);
+            let popup = self
+                .completion
+                .popup
+                .as_ref()
+                .map(|popup| completion::view(popup, &document.content));
+            scrollable(
+                stack![
+                    row![
+                        gutter::view(
+                            line_count,
+                            document.content.cursor().position.line,
+                            document.error_line,
+                        ),
+                        self.code_editor(mode, width)
+                    ]
+                    .spacing(gutter::SPACING)
+                ]
+                .push(popup),
+            )
+            .id(gutter::SCROLL_ID)
+            .direction(scrollable::Direction::Both {
+                vertical:   scrollable::Scrollbar::default(),
+                horizontal: scrollable::Scrollbar::default(),
+            })
+            .on_scroll(Message::EditorScrolled)
+            .height(Length::Fill)
+            .into()
+        });
 
+        let goto_line = self.goto_line.as_ref().map(|input| {
+            iced::widget::text_input("Go to line", input)
+                .id("goto_line")
+                .on_input(Message::GoToLineInput)
+                .on_submit(Message::GoToLineSubmitted)
+                .size(12)
+        });
+        let find = self.find.as_ref().map(|find| find::view(find, &document.content));
+        let command_line = self.vim.command_line.as_deref().map(vim::view);
+        let palette = self.palette.as_ref().map(|palette| palette::view(self, palette));
+        let references = self.references.as_ref().map(definition::view);
+        let recent_files = self
+            .show_recent_files
+            .then(|| recent_files::view(&self.recent_files));
+
+        column![]
+            .push(palette)
+            .push(recent_files)
+            .push(goto_line)
+            .push(command_line)
+            .push(references)
+            .push(find)
+            .push(editor)
+            .spacing(5)
+            .into()
     }
     fn preview_pane(&self) -> Element<'_, Message> {
-        if let Some(handle) = &self.svg_handle {
-            // FIX: Clone the handle here.
-            // The `svg` widget consumes the handle, it does not take a reference.
-            container(svg(handle.clone()).width(Length::Fill).height(Length::Fill))
+        let document = self.doc();
+        if let (Some(handle), Some(size)) = (&document.svg_handle, document.svg_size) {
+            let zoom_label = match document.preview_view.zoom {
+                Some(zoom) => format!("{:.0}%", zoom * 100.0),
+                None => String::from("Fit"),
+            };
+            let controls = row![
+                space::horizontal(),
+                iced::widget::text(zoom_label)
+                    .size(12)
+                    .color(Color::BLACK),
+                button("Fit").on_press(Message::PreviewChanged(preview::View::fit())),
+                button("1:1").on_press(Message::PreviewChanged(preview::View::actual_size())),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(5);
+
+            let preview = canvas(preview::Preview::new(handle, size, document.preview_view))
+                .width(Length::Fill)
+                .height(Length::Fill);
+
+            container(stack![preview, controls])
                 .style(|_theme| container::Style {
                     // Force background to white
                     background: Some(Color::WHITE.into()),
@@ -428,6 +1065,7 @@ impl Editor {
     }
     fn debug_overlay(&self) -> Element<'_, Message> {
         let code = self
+            .doc()
             .pikchr_code
             .clone()
             .map(|i| i.clone().into_inner())
@@ -437,11 +1075,38 @@ impl Editor {
         let overlay_bg = |t: &Theme| t.palette().background.scale_alpha(0.7);
         let border_color = |t: &Theme| t.palette().background.inverse();
 
+        // Fed line by line like the editor does. Spans can't pick colours
+        // when drawn, they're taken from the theme the window follows
+        let theme = <Theme as iced::theme::Base>::default(self.theme_mode);
+        let selected_bg = theme.extended_palette().primary.weak.color;
+        let mut highlighter = PikchrHighlighter::new(&None);
+        let mut spans: Vec<Span<'static, (), Font>> = vec![];
+        for (index, line) in code.lines().enumerate() {
+            let selected = self.doc().selected_pikchr_line == Some(index);
+            let mut piece = |piece: &str, token: Option<PikchrToken>| {
+                let color =
+                    token.and_then(|token| PikchrHighlighter::colorize(&token, &theme).color);
+                let font = token.and_then(|token| PikchrHighlighter::font(&token));
+                spans.push(
+                    span(piece.to_owned())
+                        .color_maybe(color)
+                        .font_maybe(font)
+                        .background_maybe(selected.then_some(selected_bg)),
+                );
+            };
+            let mut end = 0;
+            for (range, token) in highlighter.highlight_line(line) {
+                if end < range.start {
+                    piece(&line[end..range.start], None);
+                }
+                piece(&line[range.clone()], Some(token));
+                end = range.end;
+            }
+            piece(&format!("{}\n", &line[end..]), None);
+        }
+
         let inner_container = container(iced::widget::scrollable(
-            iced::widget::text(code)
-                .width(Length::Fill)
-                .size(12)
-                .font(iced::font::Font::MONOSPACE),
+            rich_text(spans).size(12).font(Font::MONOSPACE).width(Length::Fill),
         ))
         .style(move |theme: &Theme| container::Style {
             background: Some(iced::Background::Color(inner_bg(theme))),
@@ -470,9 +1135,15 @@ impl Editor {
         outer_container.into()
     }
     fn menu_bar(&self) -> Element<'_, Message> {
+        let with_chord = |label: &str, command: keymap::Command| {
+            match self.keymap.chord(&command) {
+                Some(chord) => format!("{} ({})", label, chord),
+                None => label.to_string(),
+            }
+        };
         let op_modes = [OperatingMode::PrologMode, OperatingMode::PikchrMode];
         let operating_mode_list =
-            pick_list(op_modes, Some(self.operating_mode), Message::RadioSelected);
+            pick_list(op_modes, Some(self.doc().operating_mode), Message::RadioSelected);
 
         let button_new = button("New").on_press(Message::NewRequested);
 
@@ -482,12 +1153,30 @@ impl Editor {
             button("Save").on_press(Message::SaveRequested)
         };
         let button_load = button("Load").on_press(Message::LoadRequested);
+        let recent_list = pick_list(
+            recent_files::menu_items(&self.recent_files),
+            None::<recent_files::RecentFile>,
+            |file| Message::LoadFileSelected(Some(file.0)),
+        )
+        .placeholder(with_chord("Recent", keymap::Command::RecentFiles));
+        let export_list = pick_list(ExportFormat::ALL, None::<ExportFormat>, Message::ExportRequested)
+            .placeholder(with_chord("Export", keymap::Command::ExportSvg));
+        let copy_list = pick_list(CopyFormat::ALL, None::<CopyFormat>, Message::CopyRequested)
+            .placeholder(with_chord("Copy", keymap::Command::CopyImage));
+
+        let button_modules = button("Modules").on_press(Message::ToggleModulesPane);
+        let button_outline = button("Outline").on_press(Message::ToggleOutlinePane);
+
+        let mode_indicator = self.keymap.modal.then(|| {
+            iced::widget::text(format!("{} {}", self.vim.mode, self.vim.pending()))
+                .font(iced::font::Font::MONOSPACE)
+        });
 
         let toggle_debug = iced::widget::toggler(self.show_debug)
-            .label("Debug Overlay (F2)")
+            .label(with_chord("Debug Overlay", keymap::Command::ToggleDebug))
             .on_toggle(|_| Message::ToggleDebugOverlay);
 
-        let toggle_watch: Element<'_, Message> = if self.current_file.is_some() {
+        let toggle_watch: Element<'_, Message> = if self.doc().current_file.is_some() {
             iced::widget::toggler(self.file_watch_mode)
                 .label("File Watch Mode")
                 .on_toggle(|_| Message::ToggleFileWatch)
@@ -500,8 +1189,14 @@ impl Editor {
             button_new,
             button_save,
             button_load,
+            recent_list,
+            export_list,
+            copy_list,
             space::horizontal(),
+            mode_indicator,
             toggle_watch,
+            button_outline,
+            button_modules,
             toggle_debug,
             operating_mode_list,
         ]
@@ -509,12 +1204,33 @@ impl Editor {
         .spacing(10)
         .into()
     }
+    fn tab_bar(&self) -> Element<'_, Message> {
+        let tabs = self.documents.iter().enumerate().map(|(index, document)| {
+            let style: fn(&Theme, button::Status) -> button::Style = if index == self.active {
+                button::primary
+            } else {
+                button::secondary
+            };
+            row![
+                button(iced::widget::text(document.title()).size(12))
+                    .style(style)
+                    .on_press(Message::TabSelected(index)),
+                button(iced::widget::text("×").size(12))
+                    .style(style)
+                    .on_press(Message::TabClosed(index)),
+            ]
+            .into()
+        });
+        iced::widget::Row::with_children(tabs).spacing(5).into()
+    }
 }
 
 #[derive(Error, Debug, Clone)]
 pub enum ApplicationError {
     #[error("SAVE UNSUCCESSFUL")]
     SaveFailure,
+    #[error("Export failed: {0}")]
+    ExportFailure(#[from] ExportError),
     #[error("PikchrProlog error: {0}")]
     PikchrPrologError(#[from] pikchr_pro::prolog::RenderError),
     #[error("Pikchr render error: {0}")]
@@ -529,22 +1245,24 @@ pub enum ApplicationError {
 
 async fn render_diagram(
     last_successful: bool,
-    mut input_rx: watch::Receiver<String>,
-    prolog_modules: PrologModules,
+    mut input_rx: watch::Receiver<Program>,
+    cancel: CancellationToken,
 ) -> Option<Result<PikchrCode, ApplicationError>> {
-    let input = input_rx.borrow_and_update().clone();
-    let input = transform_heredoc(&input);
+    let program = input_rx.borrow_and_update().clone();
+    let input = transform_heredoc(&program.source);
     if last_successful {
         tokio::time::sleep(std::time::Duration::from_millis(DEBOUNCE_MS)).await;
     }
     if input_rx.has_changed().unwrap_or(false) {
         return None;
     }
-    let result = PrologEngine::process_diagram(vec![input, prolog_modules.to_merged_string()])
-        .await
-        .map_err(|s| s.into());
+    let result =
+        PrologEngine::process_diagram_with_token(vec![input, program.library], &cancel).await;
 
-    Some(result)
+    match result {
+        Err(RenderError::Cancelled) => None,
+        result => Some(result.map_err(|s| s.into())),
+    }
 }
 
 async fn render_pikchr(
@@ -560,8 +1278,10 @@ async fn render_pikchr(
         return None;
     }
 
+    // Object ids let clicks on the preview find their source
+    let flags = pikchr::PLAINTEXT_ERRORS | pikchr::OBJECT_IDS;
     let result =
-        tokio::task::spawn_blocking(move || match pikchr::render(&input.into_inner(), None, 1) {
+        tokio::task::spawn_blocking(move || match pikchr::render(&input.into_inner(), None, flags) {
             Ok(pik) if pik.is_error() => Err(ApplicationError::PikchrError(pik.into_string())),
             Ok(pik) if pik.is_empty() => Err(ApplicationError::PikchrEmpty),
             Ok(pik) => Ok(inject_svg_style(pik.into_string())),
@@ -13,10 +13,19 @@
 
 use std::path::PathBuf;
 
-use iced::{keyboard::Modifiers, widget::{pane_grid, text_editor}};
+use iced::{
+    Point,
+    keyboard::{Key, Modifiers},
+    theme,
+    widget::{pane_grid, scrollable::Viewport, text_editor},
+};
 use pikchr_pro::types::PikchrCode;
 
-use crate::{ApplicationError, OperatingMode};
+use crate::{
+    ApplicationError, OperatingMode, clipboard::CopyFormat, definition::Location,
+    document::DocumentId,
+    export::ExportFormat, preview, prolog_source::Indicator,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum EditorAction {
@@ -25,34 +34,153 @@ pub enum EditorAction {
     NewlineIndent,
 }
 
+#[derive(Debug, Clone)]
+pub enum FindAction {
+    Requested,
+    QueryChanged(String),
+    ReplacementChanged(String),
+    CaseSensitiveToggled(bool),
+    RegexToggled(bool),
+    Next,
+    Previous,
+    /// Replaces the selected match and moves on to the next one
+    Replace,
+    ReplaceAll,
+}
+
+#[derive(Debug, Clone)]
+pub enum PaletteAction {
+    Requested,
+    QueryChanged(String),
+    /// Moves the highlight down
+    Next,
+    /// Moves the highlight up
+    Previous,
+    /// Runs the highlighted entry
+    Submitted,
+    /// Runs entry at index, as listed
+    Picked(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum CompletionAction {
+    /// Opens the popup right away
+    Requested,
+    /// Typing paused, opens the popup unless more was typed since
+    Paused(u64),
+    Next,
+    Previous,
+    /// Inserts the highlighted item
+    Accepted,
+    /// Inserts item at index, as listed
+    Picked(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum DefinitionAction {
+    /// Definition of the name under the cursor
+    GoTo,
+    /// Lists uses of the name under the cursor
+    References,
+    Show(Location),
+}
+
+/// Key taken over by modal editing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VimKey {
+    Char(char),
+    Escape,
+    Enter,
+    Backspace,
+}
+
+#[derive(Debug, Clone)]
+pub enum VimAction {
+    Key(VimKey),
+    /// `:` command line edited
+    CommandInput(String),
+    CommandSubmitted,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     /// Destructive event coming from editor
     Edit(text_editor::Action),
+    CopyRequested(CopyFormat),
+    /// Closes whatever bar is open
+    Dismiss,
     EditBatch(Vec<text_editor::Action>),
+    EditorScrolled(Viewport),
+    ExportFileSelected(ExportFormat, Option<PathBuf>),
+    ExportRequested(ExportFormat),
+    GoToLineInput(String),
+    GoToLineRequested,
+    GoToLineSubmitted,
+    /// Moves cursor to the line of the last error
+    JumpToError,
+    /// Moves cursor to the start of a 0-based line
+    JumpToLine(usize),
     LoadFileSelected(Option<PathBuf>),
+    /// Toggles recent files list over the editor
+    RecentFilesRequested,
     LoadRequested,
     ModifiersChanged(Modifiers),
+    /// Key press no widget handled, global bindings are looked up
+    KeyPressed(Key, Modifiers),
+    /// Keybindings settings file changed on disk
+    SettingsChanged,
     NewRequested,
-    PikchrFinished(Option<Result<String, ApplicationError>>),
-    PrologFinished(Option<Result<PikchrCode, ApplicationError>>),
+    PikchrFinished(DocumentId, Option<Result<String, ApplicationError>>),
+    PreviewChanged(preview::View),
+    /// Click on the diagram, in SVG pixels
+    PreviewClicked(Point),
+    PrologFinished(DocumentId, Option<Result<PikchrCode, ApplicationError>>),
     RadioSelected(OperatingMode),
     RefreshTick,
     RunLogic,
-    RunPikchr(PikchrCode),
-    RunProlog(String),
-    SaveFileSelected(Option<PathBuf>),
-    SaveFinished,
+    RunPikchr(DocumentId, PikchrCode),
+    RunProlog(DocumentId, String),
+    SaveFileSelected(DocumentId, Option<PathBuf>),
+    SaveFinished(DocumentId),
+    /// Saves the active document
     SaveRequested,
     SaveAsRequested,
     ShowError(ApplicationError),
     SaveTick,
     ToggleDebugOverlay,
+    ToggleModulesPane,
+    ToggleOutlinePane,
+    /// Folds or unfolds a predicate in the outline
+    OutlineGroupToggled(Indicator),
+    ModuleToggled(String, bool),
+    /// Shows module source in modules pane
+    ModuleViewed(String),
+    /// A module directory changed on disk
+    ModulesChanged,
+    UserModulesDirRequested,
+    UserModulesDirSelected(Option<PathBuf>),
     Undo,
     Redo,
     ToggleFileWatch,
     PaneResized(pane_grid::ResizeEvent),
     EditorAction(EditorAction),
+    Find(FindAction),
+    Vim(VimAction),
+    Palette(PaletteAction),
+    Completion(CompletionAction),
+    Definition(DefinitionAction),
     LoadedFileChanged,
+    TabSelected(usize),
+    TabClosed(usize),
+    /// Closes the active tab
+    CloseTabRequested,
+    /// Closes the tab once its document is saved
+    SaveAndCloseRequested(DocumentId),
+    /// Closes the tab, dropping unsaved changes
+    DiscardAndCloseRequested(DocumentId),
+    NextTab,
+    PreviousTab,
+    /// System switched between light and dark
+    ThemeModeChanged(theme::Mode),
     Nothing(()),
 }
@@ -0,0 +1,125 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Side pane listing Prolog modules of the active document.
+
+use std::path::PathBuf;
+
+use iced::{
+    Alignment, Color, Element, Font, Length, Task, Theme,
+    widget::{
+        button, checkbox, column, container,
+        operation::{self, AbsoluteOffset},
+        rich_text, row, scrollable, span, text,
+        text::Span,
+    },
+};
+
+use crate::{
+    messages::Message,
+    prolog_modules::{ModuleOrigin, PrologModules},
+};
+
+const TEXT_SIZE: f32 = 12.0;
+/// `LineHeight::default()` is 1.3 of the text size
+const LINE_HEIGHT: f32 = TEXT_SIZE * 1.3;
+const SOURCE_ID: &str = "module_source";
+const HIGHLIGHT_COLOR: Color = Color::from_rgba(1.0, 0.8, 0.0, 0.35);
+
+/// Checkbox per module and source of the `viewed` one, with its
+/// `highlighted` line.
+pub fn view<'a>(
+    modules: &'a PrologModules,
+    viewed: Option<&str>,
+    highlighted: Option<usize>,
+    user_dir: Option<PathBuf>,
+) -> Element<'a, Message> {
+    let entries = modules.available_modules.iter().map(|(name, module)| {
+        let style: fn(&Theme, button::Status) -> button::Style = if viewed == Some(name.as_str()) {
+            button::primary
+        } else {
+            button::text
+        };
+        let label = match module.origin {
+            ModuleOrigin::Bundled => name.clone(),
+            ModuleOrigin::User => format!("{} (user)", name),
+            ModuleOrigin::Project => format!("{} (project)", name),
+        };
+        row![
+            checkbox(modules.is_enabled(name))
+                .on_toggle(move |enabled| Message::ModuleToggled(name.clone(), enabled)),
+            button(text(label).size(TEXT_SIZE))
+                .style(style)
+                .width(Length::Fill)
+                .on_press(Message::ModuleViewed(name.clone())),
+        ]
+        .align_y(Alignment::Center)
+        .into()
+    });
+
+    let user_dir = user_dir
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or(String::from("none"));
+    let header = column![
+        text("Prolog modules").size(TEXT_SIZE),
+        row![
+            text(format!("User folder: {}", user_dir))
+                .size(TEXT_SIZE)
+                .width(Length::Fill),
+            button(text("Change").size(TEXT_SIZE)).on_press(Message::UserModulesDirRequested),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(5),
+    ]
+    .spacing(5);
+
+    let source = viewed
+        .and_then(|name| modules.available_modules.get(name))
+        .map(|module| {
+            let lines: Vec<Span<'a, (), Font>> = module
+                .code
+                .split_inclusive('\n')
+                .enumerate()
+                .map(|(line, code)| {
+                    let code = span(code);
+                    if highlighted == Some(line) {
+                        code.background(HIGHLIGHT_COLOR)
+                    } else {
+                        code
+                    }
+                })
+                .collect();
+            container(
+                scrollable(
+                    rich_text(lines)
+                        .font(Font::MONOSPACE)
+                        .size(TEXT_SIZE)
+                        .width(Length::Fill),
+                )
+                .id(SOURCE_ID),
+            )
+            .height(Length::Fill)
+        });
+
+    column![header, column(entries)]
+        .push(source)
+        .spacing(10)
+        .padding(5)
+        .into()
+}
+
+/// Scrolls the viewed module's source to `line`, with a few lines above it.
+pub fn scroll_to_line(line: usize) -> Task<Message> {
+    let y = line.saturating_sub(3) as f32 * LINE_HEIGHT;
+    operation::scroll_to(SOURCE_ID, AbsoluteOffset { x: None, y: Some(y) })
+}
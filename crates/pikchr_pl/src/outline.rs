@@ -0,0 +1,180 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Side pane listing clause heads of the active document, grouped by
+//! predicate.
+
+use std::collections::BTreeSet;
+
+use iced::{
+    Alignment, Element, Font, Length, Theme,
+    font::Weight,
+    widget::{button, column, row, scrollable, space, text},
+};
+
+use crate::{messages::Message, prolog_source::Indicator};
+
+const TEXT_SIZE: f32 = 12.0;
+/// Room for the fold button, so names line up without one
+const FOLD_WIDTH: f32 = 24.0;
+
+/// Clauses of one predicate, in source order.
+struct Group {
+    indicator: Indicator,
+    /// 0-based lines of the heads
+    lines:     Vec<usize>,
+}
+
+/// Groups ordered by their first clause.
+fn groups(source: &str) -> Vec<Group> {
+    let mut groups: Vec<Group> = vec![];
+    for clause in crate::prolog_source::clauses(source) {
+        match groups.iter_mut().find(|group| group.indicator == clause.indicator) {
+            Some(group) => group.lines.push(clause.line),
+            None => {
+                groups.push(Group {
+                    indicator: clause.indicator,
+                    lines:     vec![clause.line],
+                })
+            },
+        }
+    }
+    groups
+}
+
+/// What the diagram is rendered from.
+fn is_entry_point(indicator: &Indicator) -> bool {
+    indicator.nonterminal && indicator.name == "diagram" && indicator.arity == 0
+}
+
+/// Predicates of `source`, `None` outside of Prolog mode. The clause around
+/// `cursor_line` is highlighted, folded predicates only show their header.
+pub fn view<'a>(
+    source: Option<&str>,
+    collapsed: &BTreeSet<Indicator>,
+    cursor_line: usize,
+) -> Element<'a, Message> {
+    let Some(source) = source else {
+        return column![text("Outline lists Prolog clauses").size(TEXT_SIZE)]
+            .padding(5)
+            .into();
+    };
+    let groups = groups(source);
+    let empty = groups.is_empty();
+    // Last head at or above the cursor
+    let current = groups
+        .iter()
+        .flat_map(|group| group.lines.iter().copied())
+        .filter(|&line| line <= cursor_line)
+        .max();
+    let style = move |line: usize| -> fn(&Theme, button::Status) -> button::Style {
+        if current == Some(line) {
+            button::primary
+        } else {
+            button::text
+        }
+    };
+
+    let entries = groups.into_iter().map(|group| {
+        let folded = collapsed.contains(&group.indicator);
+        let first = group.lines[0];
+        let fold: Element<'a, Message> = if group.lines.len() > 1 {
+            button(text(if folded { "▸" } else { "▾" }).size(TEXT_SIZE))
+                .style(button::text)
+                .width(FOLD_WIDTH)
+                .on_press(Message::OutlineGroupToggled(group.indicator.clone()))
+                .into()
+        } else {
+            space::horizontal().width(FOLD_WIDTH).into()
+        };
+        let label = text(group.indicator.to_string()).size(TEXT_SIZE);
+        let (label, detail) = if is_entry_point(&group.indicator) {
+            let bold = Font {
+                weight: Weight::Bold,
+                ..Font::DEFAULT
+            };
+            (label.font(bold), String::from("diagram"))
+        } else {
+            (label, format!("{}", group.lines.len()))
+        };
+        let header = row![
+            fold,
+            button(row![label, space::horizontal(), text(detail).size(TEXT_SIZE)])
+                .style(style(first))
+                .width(Length::Fill)
+                .on_press(Message::JumpToLine(first)),
+        ]
+        .align_y(Alignment::Center);
+
+        let clauses = (!folded && group.lines.len() > 1).then(|| {
+            column(group.lines.into_iter().map(|line| {
+                row![
+                    space::horizontal().width(FOLD_WIDTH * 2.0),
+                    button(text(format!("line {}", line + 1)).size(TEXT_SIZE))
+                        .style(style(line))
+                        .width(Length::Fill)
+                        .on_press(Message::JumpToLine(line)),
+                ]
+                .into()
+            }))
+        });
+        column![header].push(clauses).into()
+    });
+
+    let list: Element<'a, Message> = if empty {
+        text("Nothing defined yet").size(TEXT_SIZE).into()
+    } else {
+        scrollable(column(entries)).height(Length::Fill).into()
+    };
+    column![text("Outline").size(TEXT_SIZE), list]
+        .spacing(10)
+        .padding(5)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clauses_are_grouped_by_predicate_in_source_order() {
+        let source = "diagram --> box.\n\
+                      box --> [].\n\
+                      diagram --> [].\n\
+                      size(1, 2).\n\
+                      box --> [x].\n";
+        let found: Vec<(String, Vec<usize>)> = groups(source)
+            .into_iter()
+            .map(|group| (group.indicator.to_string(), group.lines))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("diagram//0".to_string(), vec![0, 2]),
+                ("box//0".to_string(), vec![1, 4]),
+                ("size/2".to_string(), vec![3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_diagram_nonterminal_is_the_entry_point() {
+        let groups = groups("diagram --> [].\ndiagram(_, _).\n");
+        let marked: Vec<bool> = groups
+            .iter()
+            .map(|group| is_entry_point(&group.indicator))
+            .collect();
+        assert_eq!(groups[1].indicator.to_string(), "diagram/2");
+        assert_eq!(marked, [true, false]);
+    }
+}
@@ -0,0 +1,276 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Command palette, lists commands and nonterminals of the current file.
+
+use std::{cmp::Reverse, collections::BTreeSet};
+
+use iced::{
+    Element, Length, Task, Theme,
+    keyboard::{Key, key::Named},
+    widget::{button, column, operation, row, space, text, text_input},
+};
+
+use crate::{
+    OperatingMode,
+    editor_state::Editor,
+    keybindings::keymap::{Command, Keymap},
+    messages::{Message, PaletteAction},
+    prolog_source,
+};
+
+pub const QUERY_ID: &str = "palette_query";
+const TEXT_SIZE: f32 = 12.0;
+const MAX_SHOWN: usize = 12;
+
+#[derive(Debug, Clone, Default)]
+pub struct PaletteState {
+    pub query: String,
+    /// Highlighted entry, index into matching ones
+    selected:  usize,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Command(Command),
+    /// 0-based line of a clause
+    Line(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    label:  String,
+    /// Keybinding or line number
+    detail: String,
+    target: Target,
+}
+
+/// Every entry, commands first.
+fn entries(keymap: &Keymap, source: Option<&str>) -> Vec<Entry> {
+    let commands = Command::PALETTE.iter().map(|(command, label)| Entry {
+        label:  label.to_string(),
+        detail: keymap
+            .chord(command)
+            .map(|chord| chord.to_string())
+            .unwrap_or_default(),
+        target: Target::Command(command.clone()),
+    });
+    let mut seen = BTreeSet::new();
+    let clauses = source
+        .map(prolog_source::clauses)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|clause| clause.indicator.nonterminal)
+        // First clause of each nonterminal
+        .filter(|clause| seen.insert(clause.indicator.clone()))
+        .map(|clause| Entry {
+            label:  clause.indicator.to_string(),
+            detail: format!("line {}", clause.line + 1),
+            target: Target::Line(clause.line),
+        });
+    commands.chain(clauses).collect()
+}
+
+/// Characters of `query` in order, anywhere in `label`. Higher is better:
+/// consecutive runs and word starts count extra.
+fn score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + label[position..].iter().position(|&l| l == c)?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 3;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// Entries matching `query`, best first.
+fn matching(editor: &Editor, query: &str) -> Vec<Entry> {
+    let document = editor.doc();
+    let source = (document.operating_mode == OperatingMode::PrologMode)
+        .then(|| document.content.text());
+    let mut scored: Vec<(i32, Entry)> = entries(&editor.keymap, source.as_deref())
+        .into_iter()
+        .filter_map(|entry| Some((score(query, &entry.label)?, entry)))
+        .collect();
+    // Stable, ties keep commands before nonterminals
+    scored.sort_by_key(|(score, _)| Reverse(*score));
+    scored.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Up and down move the highlight while the palette is open.
+pub fn navigation(key: &Key) -> Option<PaletteAction> {
+    match key.as_ref() {
+        Key::Named(Named::ArrowDown) => Some(PaletteAction::Next),
+        Key::Named(Named::ArrowUp) => Some(PaletteAction::Previous),
+        _ => None,
+    }
+}
+
+pub fn handle(editor: &mut Editor, action: PaletteAction) -> Task<Message> {
+    match action {
+        PaletteAction::Requested => {
+            editor.palette = Some(PaletteState::default());
+            operation::focus(QUERY_ID)
+        },
+        PaletteAction::QueryChanged(query) => {
+            if let Some(palette) = editor.palette.as_mut() {
+                palette.query = query;
+                palette.selected = 0;
+            }
+            Task::none()
+        },
+        PaletteAction::Next => {
+            let count = editor
+                .palette
+                .as_ref()
+                .map_or(0, |palette| matching(editor, &palette.query).len());
+            if let Some(palette) = editor.palette.as_mut() {
+                palette.selected = (palette.selected + 1).min(count.saturating_sub(1));
+            }
+            Task::none()
+        },
+        PaletteAction::Previous => {
+            if let Some(palette) = editor.palette.as_mut() {
+                palette.selected = palette.selected.saturating_sub(1);
+            }
+            Task::none()
+        },
+        PaletteAction::Submitted => {
+            let selected = editor.palette.as_ref().map_or(0, |palette| palette.selected);
+            run(editor, selected)
+        },
+        PaletteAction::Picked(index) => run(editor, index),
+    }
+}
+
+fn run(editor: &mut Editor, index: usize) -> Task<Message> {
+    let Some(palette) = editor.palette.take() else {
+        return Task::none();
+    };
+    let mut matching = matching(editor, &palette.query);
+    if matching.is_empty() {
+        return operation::focus("editor");
+    }
+    let entry = matching.swap_remove(index.min(matching.len() - 1));
+    let task = match entry.target {
+        Target::Command(command) => command.message().map_or_else(Task::none, Task::done),
+        Target::Line(line) => editor.jump_to_line(line),
+    };
+    Task::batch([operation::focus("editor"), task])
+}
+
+/// Query input with matching entries below it.
+pub fn view<'a>(editor: &Editor, palette: &'a PaletteState) -> Element<'a, Message> {
+    let matching = matching(editor, &palette.query);
+    let selected = palette.selected.min(matching.len().saturating_sub(1));
+    // Keeps the highlighted entry in view
+    let skip = (selected + 1).saturating_sub(MAX_SHOWN);
+    let entries = matching
+        .into_iter()
+        .enumerate()
+        .skip(skip)
+        .take(MAX_SHOWN)
+        .map(|(index, entry)| {
+            let style: fn(&Theme, button::Status) -> button::Style = if index == selected {
+                button::primary
+            } else {
+                button::text
+            };
+            button(
+                row![
+                    text(entry.label).size(TEXT_SIZE),
+                    space::horizontal(),
+                    text(entry.detail).size(TEXT_SIZE),
+                ]
+                .spacing(10),
+            )
+            .style(style)
+            .width(Length::Fill)
+            .on_press(Message::Palette(PaletteAction::Picked(index)))
+            .into()
+        });
+
+    column![
+        text_input("Command or nonterminal", &palette.query)
+            .id(QUERY_ID)
+            .on_input(|query| Message::Palette(PaletteAction::QueryChanged(query)))
+            .on_submit(Message::Palette(PaletteAction::Submitted))
+            .size(TEXT_SIZE),
+        column(entries),
+    ]
+    .spacing(5)
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    fn labels(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.label.as_str()).collect()
+    }
+
+    #[test]
+    fn score_matches_in_order_only() {
+        assert!(score("gtl", "Go to line").is_some());
+        assert!(score("GTL", "go to line").is_some());
+        assert_eq!(score("lg", "Go to line"), None);
+        assert_eq!(score("x", "Go to line"), None);
+    }
+
+    #[test]
+    fn score_rewards_consecutive_and_word_starts() {
+        // Word starts beat letters inside a word
+        assert!(score("t", "Go to line") > score("o", "Go to line"));
+        // Consecutive letters beat scattered ones
+        assert!(score("to", "Go to line") > score("te", "Go to line"));
+        assert_eq!(score("go", "Go to line"), Some(7));
+    }
+
+    #[test]
+    fn ties_keep_commands_before_nonterminals() {
+        let mut editor = Editor::default();
+        editor.documents = vec![Document::new(0, "save --> [].\n", OperatingMode::PrologMode)];
+        assert_eq!(labels(&matching(&editor, "save")), ["Save", "Save as", "save//0"]);
+    }
+
+    #[test]
+    fn one_entry_per_nonterminal_at_its_first_clause() {
+        let source = "diagram --> box.\n\
+                      box --> [].\n\
+                      diagram --> [].\n\
+                      size(1, 2).\n";
+        let lines: Vec<(String, usize)> = entries(&Keymap::default(), Some(source))
+            .into_iter()
+            .filter_map(|entry| match entry.target {
+                Target::Line(line) => Some((entry.label, line)),
+                Target::Command(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [("diagram//0".to_string(), 0), ("box//0".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,258 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+use iced::{
+    Point, Rectangle, Renderer, Size, Theme, Vector, mouse,
+    widget::{canvas, svg},
+};
+use pikchr_pro::pikchr;
+use resvg::usvg;
+
+use crate::{SPACE_MONO_BYTES, messages::Message};
+
+const ZOOM_MIN: f32 = 0.1;
+const ZOOM_MAX: f32 = 16.0;
+/// Zoom multiplier for a single wheel notch
+const ZOOM_STEP: f32 = 1.1;
+/// Trackpads scroll by pixels, this many make up one notch
+const PIXELS_PER_LINE: f32 = 50.0;
+/// Lines are only a couple pixels wide, be generous when clicking them
+const HIT_SLOP: f32 = 4.0;
+
+/// How diagram is placed within the preview pane.
+///
+/// Lives in the editor rather than in the canvas state, so re-renders
+/// (which replace the SVG handle) don't reset it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct View {
+    /// `None` fits diagram into the pane
+    pub zoom:   Option<f32>,
+    /// Diagram's top-left corner within the pane, `None` centers it
+    pub offset: Option<Vector>,
+}
+
+impl View {
+    pub fn fit() -> Self {
+        Self::default()
+    }
+
+    pub fn actual_size() -> Self {
+        Self::with_zoom(Some(1.0))
+    }
+
+    pub fn with_zoom(zoom: Option<f32>) -> Self {
+        Self {
+            zoom:   zoom.map(|z| z.clamp(ZOOM_MIN, ZOOM_MAX)),
+            offset: None,
+        }
+    }
+
+    /// Zoom and offset used to draw diagram of `size` in `bounds`.
+    fn resolve(&self, size: Size, bounds: Size) -> (f32, Vector) {
+        let zoom = self.zoom.unwrap_or_else(|| {
+            (bounds.width / size.width)
+                .min(bounds.height / size.height)
+                .clamp(ZOOM_MIN, ZOOM_MAX)
+        });
+        let offset = self.offset.unwrap_or_else(|| {
+            Vector::new(
+                (bounds.width - size.width * zoom) / 2.0,
+                (bounds.height - size.height * zoom) / 2.0,
+            )
+        });
+        (zoom, offset)
+    }
+}
+
+/// Natural size of Pikchr generated SVG.
+///
+/// Pikchr puts `width`/`height` only when `scale` is set, otherwise
+/// `viewBox` is the size.
+pub fn natural_size(svg: &str) -> Option<Size> {
+    let root = &svg[..svg.find('>')?];
+
+    let size = match (attribute(root, "width"), attribute(root, "height")) {
+        (Some(width), Some(height)) => Size::new(width.parse().ok()?, height.parse().ok()?),
+        _ => {
+            let mut view_box = attribute(root, "viewBox")?
+                .split_whitespace()
+                .skip(2)
+                .map(str::parse::<f32>);
+            Size::new(view_box.next()?.ok()?, view_box.next()?.ok()?)
+        },
+    };
+    (size.width > 0.0 && size.height > 0.0).then_some(size)
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+/// Source line of the smallest object under `point` (in SVG pixels).
+///
+/// Needs SVG rendered with `pikchr::OBJECT_IDS`.
+pub fn object_at(svg: &str, point: Point) -> Option<usize> {
+    let mut options = usvg::Options::default();
+    // Without fonts text is dropped and text-only objects can't be hit
+    options
+        .fontdb_mut()
+        .load_font_data(SPACE_MONO_BYTES.to_vec());
+    let tree = usvg::Tree::from_data(svg.as_bytes(), &options).ok()?;
+
+    let mut best = None;
+    find_objects(tree.root(), point, &mut best);
+    best.map(|(_area, line)| line)
+}
+
+fn find_objects(group: &usvg::Group, point: Point, best: &mut Option<(f32, usize)>) {
+    for node in group.children() {
+        let usvg::Node::Group(group) = node else {
+            continue;
+        };
+        if let Some(line) = pikchr::object_line(group.id()) {
+            let bbox = group.abs_stroke_bounding_box();
+            let bounds = Rectangle::new(
+                Point::new(bbox.x(), bbox.y()),
+                Size::new(bbox.width(), bbox.height()),
+            )
+            .expand(HIT_SLOP);
+            let area = bounds.area();
+            if bounds.contains(point) && best.is_none_or(|(best_area, _)| area < best_area) {
+                *best = Some((area, line));
+            }
+        }
+        find_objects(group, point, best);
+    }
+}
+
+/// Zoomable and pannable SVG preview.
+pub struct Preview<'a> {
+    handle: &'a svg::Handle,
+    size:   Size,
+    view:   View,
+}
+
+impl<'a> Preview<'a> {
+    pub fn new(handle: &'a svg::Handle, size: Size, view: View) -> Self {
+        Self { handle, size, view }
+    }
+}
+
+/// Mouse button held over the preview
+pub struct Drag {
+    last:  Point,
+    moved: bool,
+}
+
+impl canvas::Program<Message> for Preview<'_> {
+    type State = Option<Drag>;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: &canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<canvas::Action<Message>> {
+        let (zoom, offset) = self.view.resolve(self.size, bounds.size());
+
+        let view = match event {
+            canvas::Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let position = cursor.position_in(bounds)?;
+                let notches = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => *y,
+                    mouse::ScrollDelta::Pixels { y, .. } => *y / PIXELS_PER_LINE,
+                };
+                let new_zoom = (zoom * ZOOM_STEP.powf(notches)).clamp(ZOOM_MIN, ZOOM_MAX);
+
+                // Keep the point under cursor in place
+                let anchor = position - Point::ORIGIN;
+                View {
+                    zoom:   Some(new_zoom),
+                    offset: Some(anchor - (anchor - offset) * (new_zoom / zoom)),
+                }
+            },
+            canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                cursor.position_in(bounds)?;
+                *state = cursor.position().map(|last| Drag { last, moved: false });
+                return Some(canvas::Action::capture());
+            },
+            canvas::Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let drag = state.as_mut()?;
+                let delta = *position - drag.last;
+                drag.last = *position;
+                drag.moved = true;
+                View {
+                    zoom:   Some(zoom),
+                    offset: Some(offset + delta),
+                }
+            },
+            canvas::Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                let drag = state.take()?;
+                let position = cursor.position_in(bounds);
+                return match position {
+                    Some(position) if !drag.moved => {
+                        let point =
+                            Point::ORIGIN + (position - Point::ORIGIN - offset) * (1.0 / zoom);
+                        Some(canvas::Action::publish(Message::PreviewClicked(point)).and_capture())
+                    },
+                    _ => Some(canvas::Action::capture()),
+                };
+            },
+            canvas::Event::Mouse(mouse::Event::CursorLeft) => {
+                state.take()?;
+                return Some(canvas::Action::capture());
+            },
+            _ => return None,
+        };
+
+        Some(canvas::Action::publish(Message::PreviewChanged(view)).and_capture())
+    }
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let (zoom, offset) = self.view.resolve(self.size, bounds.size());
+
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        frame.with_clip(Rectangle::with_size(bounds.size()), |frame| {
+            frame.draw_svg(
+                Rectangle::new(Point::ORIGIN + offset, self.size * zoom),
+                self.handle,
+            );
+        });
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if state.is_some() {
+            mouse::Interaction::Grabbing
+        } else if cursor.is_over(bounds) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
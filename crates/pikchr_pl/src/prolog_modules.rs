@@ -11,54 +11,428 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::constants;
+use directories::ProjectDirs;
+
+use crate::{
+    constants,
+    prolog_source::{self, Indicator},
+};
+
+/// Off in new documents, `testing` only matters for the module test suite
+pub const DEFAULT_DISABLED: &[&str] = &["testing"];
+/// Folder next to the open file with modules of that project
+pub const PROJECT_DIR: &str = ".pikchr_pl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleOrigin {
+    /// Compiled in from `native/prolog`
+    Bundled,
+    /// User modules directory
+    User,
+    /// `.pikchr_pl/` next to the open file
+    Project,
+}
+
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub code:   Arc<str>,
+    pub origin: ModuleOrigin,
+}
+
+impl Module {
+    /// Everything defined at top level, stands in for exports of modules
+    /// declared without a list.
+    pub fn defined(&self) -> Vec<Indicator> {
+        let defined: BTreeSet<Indicator> = prolog_source::clauses(&self.code)
+            .into_iter()
+            .map(|clause| clause.indicator)
+            .collect();
+        defined.into_iter().collect()
+    }
+}
+
+/// Source linked against modules, consulted in this order.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub source:   String,
+    pub library:  String,
+    /// Shadowed library nonterminals and bad `use_module` directives
+    pub warnings: Vec<String>,
+}
 
 #[derive(Clone, Debug)]
 pub struct PrologModules {
-    pub available_modules: BTreeMap<&'static str, &'static str>,
-    pub enabled_modules: Vec<&'static str>,
+    pub available_modules: BTreeMap<String, Module>,
+    /// Disabled rather than enabled are kept, so modules showing up later
+    /// (e.g. added to a module directory) start enabled.
+    disabled_modules: BTreeSet<String>,
 }
 
 impl PrologModules {
     pub fn new() -> Self {
-        let available_modules: BTreeMap<&'static str, &'static str> =
-            constants::PROLOG_MODULES.iter().cloned().collect();
-        let enabled_modules = available_modules.keys().copied().collect();
+        let available_modules = constants::PROLOG_MODULES
+            .iter()
+            .map(|(name, code)| {
+                let module = Module {
+                    code:   Arc::from(*code),
+                    origin: ModuleOrigin::Bundled,
+                };
+                (name.to_string(), module)
+            })
+            .collect();
         Self {
             available_modules,
-            enabled_modules,
+            disabled_modules: BTreeSet::new(),
+        }
+    }
+    pub fn with_disabled(disabled: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut modules = Self::new();
+        modules.set_disabled(disabled);
+        modules
+    }
+    /// Bundled modules, then the user and project ones. Later ones replace
+    /// earlier ones of the same name.
+    pub fn load(user_dir: Option<&Path>, file: Option<&Path>) -> Self {
+        let mut modules = Self::new();
+        if let Some(dir) = user_dir {
+            modules.load_dir(dir, ModuleOrigin::User);
+        }
+        if let Some(dir) = file.and_then(project_dir) {
+            modules.load_dir(&dir, ModuleOrigin::Project);
+        }
+        modules
+    }
+    /// Rescans module directories, keeping the selection.
+    pub fn reload(&mut self, user_dir: Option<&Path>, file: Option<&Path>) {
+        let disabled = std::mem::take(&mut self.disabled_modules);
+        *self = Self::load(user_dir, file);
+        self.disabled_modules = disabled;
+    }
+    fn load_dir(&mut self, dir: &Path, origin: ModuleOrigin) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().is_none_or(|ext| ext != "pl") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            // Might be mid-write, next change event picks it up
+            let Ok(code) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let module = Module {
+                code: Arc::from(code),
+                origin,
+            };
+            self.available_modules.insert(name.to_string(), module);
+        }
+    }
+    pub fn set_disabled(&mut self, disabled: impl IntoIterator<Item = impl AsRef<str>>) {
+        self.disabled_modules = disabled
+            .into_iter()
+            .map(|module| module.as_ref().to_string())
+            .collect();
+    }
+    pub fn is_enabled(&self, module: &str) -> bool {
+        self.available_modules.contains_key(module) && !self.disabled_modules.contains(module)
+    }
+    pub fn set_enabled(&mut self, module: &str, enabled: bool) -> &Self {
+        if enabled {
+            self.enable(module)
+        } else {
+            self.disable(module)
         }
     }
-    pub fn to_merged_string(&self) -> String {
-        self.available_modules
+    pub fn disabled(&self) -> Vec<String> {
+        self.disabled_modules.iter().cloned().collect()
+    }
+    /// Program for `source`: the source itself, with `use_module`
+    /// directives of our modules commented out as Trealla would look for
+    /// files, then the enabled modules.
+    ///
+    /// Exports of imported modules are reached through bridge clauses in
+    /// `user`, so a definition in `source` wins over them. Without
+    /// `use_module` directives every enabled module is imported.
+    pub fn link(&self, source: &str) -> Program {
+        let mut warnings = vec![];
+        let enabled: Vec<(&String, &Module)> = self
+            .available_modules
             .iter()
-            .filter(|(k, _)| self.enabled_modules.contains(k))
-            .map(|(_, v)| v)
-            .copied()
+            .filter(|(name, _)| self.is_enabled(name))
+            .collect();
+
+        // Anything else is left to Prolog
+        let used: Vec<(usize, String)> = prolog_source::used_modules(source)
+            .into_iter()
+            .filter(|(_, name)| self.available_modules.contains_key(name))
+            .collect();
+        let source = comment_out(source, used.iter().map(|(line, _)| *line));
+        let imported: Vec<&str> = if used.is_empty() {
+            enabled.iter().map(|(name, _)| name.as_str()).collect()
+        } else {
+            used.iter()
+                .filter_map(|(_, name)| {
+                    if self.is_enabled(name) {
+                        Some(name.as_str())
+                    } else {
+                        warnings.push(format!("Module {} is disabled", name));
+                        None
+                    }
+                })
+                .collect()
+        };
+
+        let defined: BTreeSet<Indicator> = prolog_source::clauses(&source)
+            .into_iter()
+            .map(|clause| clause.indicator)
+            .collect();
+        let mut bridged: BTreeMap<Indicator, String> = BTreeMap::new();
+        let mut bridges = vec![];
+        let mut prelude = vec![];
+        let mut declared = vec![];
+        for (name, module) in &enabled {
+            let Some(declaration) = prolog_source::module_declaration(&module.code) else {
+                // Loaded into `user` along with the source
+                for indicator in module.defined() {
+                    if indicator.nonterminal && defined.contains(&indicator) {
+                        warnings.push(format!(
+                            "{} is also defined in {}, clauses are merged",
+                            indicator, name
+                        ));
+                    }
+                }
+                prelude.push(&*module.code);
+                continue;
+            };
+            declared.push(&*module.code);
+            if !imported.contains(&name.as_str()) {
+                continue;
+            }
+            let exports = declaration.exports.unwrap_or_else(|| module.defined());
+            for indicator in exports {
+                if defined.contains(&indicator) {
+                    if indicator.nonterminal {
+                        warnings.push(format!(
+                            "{} shadows {}:{}",
+                            indicator, declaration.name, indicator
+                        ));
+                    }
+                } else if let Some(other) = bridged.get(&indicator) {
+                    warnings.push(format!(
+                        "{} is exported by {} and {}, using {}",
+                        indicator, other, declaration.name, other
+                    ));
+                } else {
+                    bridges.push(bridge(&declaration.name, &indicator));
+                    bridged.insert(indicator, declaration.name.clone());
+                }
+            }
+        }
+
+        // Bridges come first, while still in `user`
+        let library = [bridges.join("\n")]
+            .into_iter()
+            .chain(prelude.into_iter().map(str::to_string))
+            .chain(declared.into_iter().map(str::to_string))
             .collect::<Vec<_>>()
-            .join("\n\n")
+            .join("\n\n");
+        Program {
+            source,
+            library,
+            warnings,
+        }
     }
     pub fn disable(&mut self, module: &str) -> &Self {
-        let new_enabled: Vec<&str> = self
-            .enabled_modules
-            .iter()
-            .cloned()
-            .filter(|i| *i != module)
-            .collect();
-        self.enabled_modules = new_enabled;
+        self.disabled_modules.insert(module.to_string());
         self
     }
-
-    #[allow(unused)]
     pub fn enable(&mut self, module: &str) -> &Self {
-        if let Some((&static_key, _)) = self.available_modules.get_key_value(module) {
-            if self.enabled_modules.contains(&static_key) {
-                return self;
+        self.disabled_modules.remove(module);
+        self
+    }
+}
+
+/// Calls `module:indicator` from `user`.
+fn bridge(module: &str, indicator: &Indicator) -> String {
+    let arguments = (1..=indicator.predicate_arity())
+        .map(|n| format!("A{}", n))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let name = prolog_source::atom(&indicator.name);
+    if arguments.is_empty() {
+        format!("{} :- {}:{}.", name, module, name)
+    } else {
+        format!("{}({}) :- {}:{}({}).", name, arguments, module, name, arguments)
+    }
+}
+
+/// Keeps line numbers, errors point into the source.
+fn comment_out(source: &str, lines: impl Iterator<Item = usize>) -> String {
+    let lines: BTreeSet<usize> = lines.collect();
+    if lines.is_empty() {
+        return source.to_string();
+    }
+    source
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(line, text)| {
+            if lines.contains(&line) {
+                format!("% {}", text)
+            } else {
+                text.to_string()
             }
-            self.enabled_modules.push(static_key);
+        })
+        .collect()
+}
+
+pub fn project_dir(file: &Path) -> Option<PathBuf> {
+    Some(file.parent()?.join(PROJECT_DIR))
+}
+
+/// Used until another one is picked in modules pane.
+pub fn default_user_dir() -> Option<PathBuf> {
+    let project_dir = ProjectDirs::from("sh", "axk", "pikchr_pl")?;
+    Some(project_dir.config_dir().join("modules"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: &str = "\
+:- module(shapes, [circle//0, area/2]).
+circle --> [circle].
+area(R, A) :- A is 3 * R * R.
+hidden --> [].
+";
+
+    fn modules(modules: &[(&str, &str)]) -> PrologModules {
+        let available_modules = modules
+            .iter()
+            .map(|(name, code)| {
+                let module = Module {
+                    code:   Arc::from(*code),
+                    origin: ModuleOrigin::User,
+                };
+                (name.to_string(), module)
+            })
+            .collect();
+        PrologModules {
+            available_modules,
+            disabled_modules: BTreeSet::new(),
         }
-        self
+    }
+
+    #[test]
+    fn exports_are_bridged() {
+        let program = modules(&[("shapes", SHAPES)]).link("diagram --> circle.\n");
+        assert!(program.warnings.is_empty());
+        assert!(
+            program
+                .library
+                .starts_with("circle(A1, A2) :- shapes:circle(A1, A2).\narea(A1, A2) :- ")
+        );
+        assert!(!program.library.contains(":- shapes:hidden"));
+        assert!(program.library.ends_with(SHAPES));
+    }
+
+    #[test]
+    fn module_without_exports_bridges_everything() {
+        let program = modules(&[("arrows", ":- module(arrows).\narrow --> [].\n")]).link("");
+        assert!(
+            program
+                .library
+                .contains("arrow(A1, A2) :- arrows:arrow(A1, A2).")
+        );
+    }
+
+    #[test]
+    fn use_module_is_commented_out() {
+        let source = "\
+:- use_module(shapes).
+:- use_module(library(lists)).
+diagram --> circle.
+";
+        let program = modules(&[("shapes", SHAPES)]).link(source);
+        assert_eq!(
+            program.source,
+            "\
+% :- use_module(shapes).
+:- use_module(library(lists)).
+diagram --> circle.
+"
+        );
+    }
+
+    #[test]
+    fn use_module_picks_imported_modules() {
+        let arrows = ":- module(arrows, [arrow//0]).\narrow --> [].\n";
+        let modules = modules(&[("shapes", SHAPES), ("arrows", arrows)]);
+        let program = modules.link(":- use_module(arrows).\n");
+        assert!(program.library.contains(":- arrows:arrow("));
+        assert!(!program.library.contains(":- shapes:circle("));
+        // Still loaded, qualified calls keep working
+        assert!(program.library.contains(SHAPES));
+    }
+
+    #[test]
+    fn disabled_module_is_reported() {
+        let mut modules = modules(&[("shapes", SHAPES)]);
+        modules.disable("shapes");
+        let program = modules.link(":- use_module(shapes).\n");
+        assert_eq!(program.warnings, ["Module shapes is disabled"]);
+        assert!(!program.library.contains(SHAPES));
+    }
+
+    #[test]
+    fn source_definitions_shadow_exports() {
+        let program = modules(&[("shapes", SHAPES)]).link("circle --> [mine].\narea(_, 0).\n");
+        // Only nonterminals are worth a warning
+        assert_eq!(program.warnings, ["circle//0 shadows shapes:circle//0"]);
+        assert!(!program.library.contains(":- shapes:circle("));
+        assert!(!program.library.contains(":- shapes:area("));
+    }
+
+    #[test]
+    fn first_export_wins() {
+        let other = ":- module(other, [circle//0]).\ncircle --> [other].\n";
+        let program = modules(&[("shapes", SHAPES), ("other", other)]).link("");
+        assert_eq!(
+            program.warnings,
+            ["circle//0 is exported by other and shapes, using other"]
+        );
+        assert!(program.library.contains(":- other:circle("));
+        assert!(!program.library.contains(":- shapes:circle("));
+    }
+
+    #[test]
+    fn plain_files_merge_with_source() {
+        let program = modules(&[("plain", "box --> [plain].\n")]).link("box --> [mine].\n");
+        assert_eq!(
+            program.warnings,
+            ["box//0 is also defined in plain, clauses are merged"]
+        );
+        assert!(program.library.contains("box --> [plain]."));
+    }
+
+    #[test]
+    fn bridge_quotes_names() {
+        let indicator = "go/0".parse().unwrap();
+        assert_eq!(bridge("m", &indicator), "go :- m:go.");
+        let quoted = Indicator {
+            name:        String::from("Go"),
+            arity:       1,
+            nonterminal: false,
+        };
+        assert_eq!(bridge("m", &quoted), "'Go'(A1) :- m:'Go'(A1).");
     }
 }
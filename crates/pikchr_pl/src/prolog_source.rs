@@ -0,0 +1,417 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Just enough Prolog reading to find clause heads and directives.
+//!
+//! Clauses are expected to start at column 0, as they do in every file we
+//! ship. Anything indented is taken as a continuation of the clause above.
+
+use std::{fmt::Display, str::FromStr};
+
+/// `name/arity` for predicates, `name//arity` for nonterminals.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Indicator {
+    pub name:        String,
+    pub arity:       usize,
+    pub nonterminal: bool,
+}
+
+impl Display for Indicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = if self.nonterminal { "//" } else { "/" };
+        write!(f, "{}{}{}", self.name, separator, self.arity)
+    }
+}
+
+impl FromStr for Indicator {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, arity, nonterminal) = match s.split_once("//") {
+            Some((name, arity)) => (name, arity, true),
+            None => {
+                let (name, arity) = s.rsplit_once('/').ok_or(())?;
+                (name, arity, false)
+            },
+        };
+        let name = name.trim();
+        if !is_plain_atom(name) {
+            return Err(());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            arity: arity.trim().parse().map_err(|_| ())?,
+            nonterminal,
+        })
+    }
+}
+
+impl Indicator {
+    /// Arity of the underlying predicate, nonterminals take two list
+    /// arguments on top.
+    pub fn predicate_arity(&self) -> usize {
+        if self.nonterminal {
+            self.arity + 2
+        } else {
+            self.arity
+        }
+    }
+}
+
+/// Clause head found in source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clause {
+    pub indicator: Indicator,
+    /// 0-based line of the head
+    pub line:      usize,
+}
+
+/// `:- module(Name, Exports).`, exports are `None` for `:- module(Name).`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleDeclaration {
+    pub name:    String,
+    pub exports: Option<Vec<Indicator>>,
+}
+
+/// `[a-z][a-zA-Z0-9_]*`, written without quotes.
+pub fn is_plain_atom(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes `name` unless it can go without.
+pub fn atom(name: &str) -> String {
+    if is_plain_atom(name) {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+/// Every rule and fact head starting at column 0, in source order.
+pub fn clauses(source: &str) -> Vec<Clause> {
+    let mut clauses = vec![];
+    let mut offset = 0;
+    let mut clause_ended = true;
+    for (line, text) in source.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += text.len();
+        let code = strip_comment(text).trim_end();
+        if code.trim().is_empty() {
+            continue;
+        }
+        let starts_clause = clause_ended && !text.starts_with(char::is_whitespace);
+        clause_ended = code.ends_with('.');
+        if !starts_clause {
+            continue;
+        }
+        if let Some(indicator) = head(&source[start..]) {
+            clauses.push(Clause { indicator, line });
+        }
+    }
+    clauses
+}
+
+/// Declaration of a module file, if it has one.
+pub fn module_declaration(source: &str) -> Option<ModuleDeclaration> {
+    let (_, rest) = source.split_once(":- module(")?;
+    let (arguments, _) = arguments(rest)?;
+    let mut arguments = arguments.into_iter();
+    let name = arguments.next()?.trim().to_string();
+    if !is_plain_atom(&name) {
+        return None;
+    }
+    let exports = arguments.next().map(|list| {
+        let list = list.trim().trim_start_matches('[').trim_end_matches(']');
+        match split_top_level(list) {
+            Some(items) => {
+                items
+                    .iter()
+                    .filter_map(|item| item.parse().ok())
+                    .collect()
+            },
+            None => vec![],
+        }
+    });
+    Some(ModuleDeclaration { name, exports })
+}
+
+/// `:- use_module(name).` directives with a plain module name, as
+/// `(line, name)`. `library(...)` ones are left to Prolog.
+pub fn used_modules(source: &str) -> Vec<(usize, String)> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let rest = strip_comment(text).trim().strip_prefix(":-")?;
+            let rest = rest.trim_start().strip_prefix("use_module(")?;
+            let (name, rest) = rest.split_once(')')?;
+            let name = name.trim();
+            (is_plain_atom(name) && rest.trim() == ".").then(|| (line, name.to_string()))
+        })
+        .collect()
+}
+
+/// Lines using `name`, plain or module qualified. Occurrences in comments,
+/// quotes and at the start of a clause head don't count.
+pub fn references(source: &str, name: &str) -> Vec<usize> {
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, text)| {
+            let code = code_only(text);
+            code.match_indices(name).any(|(index, _)| {
+                let before = code[..index].chars().next_back();
+                let after = code[index + name.len()..].chars().next();
+                let is_name = |c: char| c.is_alphanumeric() || c == '_';
+                index > 0 && !before.is_some_and(is_name) && !after.is_some_and(is_name)
+            })
+        })
+        .map(|(line, _)| line)
+        .collect()
+}
+
+/// Indicator of the head at the start of `source`, if it is a rule or fact.
+fn head(source: &str) -> Option<Indicator> {
+    let name_len = source
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(source.len());
+    let name = &source[..name_len];
+    if !is_plain_atom(name) {
+        return None;
+    }
+    let mut rest = &source[name_len..];
+    let mut arity = 0;
+    if let Some(after_paren) = rest.strip_prefix('(') {
+        let (args, after) = arguments(after_paren)?;
+        arity = args.len();
+        rest = after;
+    }
+    let rest = rest.trim_start();
+    let nonterminal = if rest.starts_with("-->") {
+        true
+    } else if rest.starts_with(":-") || rest.starts_with('.') {
+        false
+    } else {
+        return None;
+    };
+    Some(Indicator {
+        name: name.to_string(),
+        arity,
+        nonterminal,
+    })
+}
+
+/// Splits arguments up to the closing parenthesis matching an already
+/// consumed opening one. Returns them with what follows the parenthesis.
+fn arguments(source: &str) -> Option<(Vec<&str>, &str)> {
+    let end = closing(source)?;
+    Some((split_top_level(&source[..end])?, &source[end + 1..]))
+}
+
+/// Index of the `)` closing the current term.
+fn closing(source: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (index, c) in unquoted(source) {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => return Some(index),
+            ')' | ']' | '}' => depth = depth.checked_sub(1)?,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits on commas outside of brackets and quotes, `None` if unbalanced.
+fn split_top_level(source: &str) -> Option<Vec<&str>> {
+    if source.trim().is_empty() {
+        return Some(vec![]);
+    }
+    let mut items = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in unquoted(source) {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                items.push(&source[start..index]);
+                start = index + 1;
+            },
+            _ => (),
+        }
+    }
+    items.push(&source[start..]);
+    Some(items)
+}
+
+/// Drops a trailing `%` comment, keeping `%` inside quotes.
+fn strip_comment(line: &str) -> &str {
+    match unquoted(line).find(|&(_, c)| c == '%') {
+        Some((index, _)) => &line[..index],
+        None => line,
+    }
+}
+
+/// `line` without its comment, quoted text blanked out.
+fn code_only(line: &str) -> String {
+    let code = strip_comment(line);
+    let mut blanked = vec![b' '; code.len()];
+    for (index, c) in unquoted(code) {
+        c.encode_utf8(&mut blanked[index..]);
+    }
+    // Whole characters on spaces
+    String::from_utf8(blanked).unwrap_or_default()
+}
+
+/// Characters outside of quotes, quotes included, with their byte index.
+/// `0'c` character codes don't open a quote.
+fn unquoted(source: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut character_code = false;
+    let mut previous = None;
+    source.char_indices().filter(move |&(_, c)| {
+        let before = previous.replace(c);
+        if character_code {
+            character_code = false;
+            return false;
+        }
+        let Some(q) = quote else {
+            match c {
+                '\'' if before == Some('0') => character_code = true,
+                '"' | '\'' | '`' => quote = Some(c),
+                _ => (),
+            }
+            return true;
+        };
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == q {
+            quote = None;
+        }
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indicators(source: &str) -> Vec<String> {
+        clauses(source)
+            .into_iter()
+            .map(|clause| format!("{} {}", clause.line, clause.indicator))
+            .collect()
+    }
+
+    #[test]
+    fn heads_of_facts_rules_and_nonterminals() {
+        let source = "fact.\nrule(X) :- fact.\nbox --> [].\nshape(A, B) --> box.\n";
+        assert_eq!(
+            indicators(source),
+            ["0 fact/0", "1 rule/1", "2 box//0", "3 shape//2"]
+        );
+    }
+
+    #[test]
+    fn continuations_and_directives_are_no_heads() {
+        let source = "\
+:- use_module(shapes).
+rule :-
+    other.
+two
+lines.
+% comment.
+after(',', 'a)').
+";
+        assert_eq!(indicators(source), ["1 rule/0", "6 after/2"]);
+    }
+
+    #[test]
+    fn clause_ends_before_comment() {
+        let source = "a. % not continued\nb.\n";
+        assert_eq!(indicators(source), ["0 a/0", "1 b/0"]);
+    }
+
+    #[test]
+    fn module_declaration_with_exports() {
+        let source = "% Shapes\n:- module(shapes, [circle//0, area/2, 'Bad'/1]).\n";
+        let declaration = module_declaration(source).unwrap();
+        assert_eq!(declaration.name, "shapes");
+        let exports: Vec<String> = declaration
+            .exports
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(exports, ["circle//0", "area/2"]);
+    }
+
+    #[test]
+    fn module_declaration_without_exports() {
+        let declaration = module_declaration(":- module(shapes).").unwrap();
+        assert_eq!(declaration.exports, None);
+        let empty = module_declaration(":- module(shapes, []).").unwrap();
+        assert_eq!(empty.exports, Some(vec![]));
+        assert_eq!(module_declaration("box --> []."), None);
+    }
+
+    #[test]
+    fn used_modules_with_plain_names() {
+        let source = "\
+:- use_module(shapes).
+:- use_module(library(lists)).
+% :- use_module(commented).
+:-use_module( arrows ).
+";
+        assert_eq!(
+            used_modules(source),
+            [(0, String::from("shapes")), (3, String::from("arrows"))]
+        );
+    }
+
+    #[test]
+    fn references_skip_heads_comments_and_quotes() {
+        let source = "\
+box --> [].
+diagram --> box, shapes:box.
+label --> ['box'].
+% box
+boxes --> [].
+";
+        assert_eq!(references(source, "box"), [1]);
+    }
+
+    #[test]
+    fn indicator_round_trips() {
+        for indicator in ["box//0", "area/2"] {
+            let parsed: Indicator = indicator.parse().unwrap();
+            assert_eq!(parsed.to_string(), indicator);
+        }
+        assert!("Box/1".parse::<Indicator>().is_err());
+        assert_eq!("box//1".parse::<Indicator>().unwrap().predicate_arity(), 3);
+    }
+
+    #[test]
+    fn atoms_are_quoted_when_needed() {
+        assert_eq!(atom("box"), "box");
+        assert_eq!(atom("Box"), "'Box'");
+        assert_eq!(atom("it's"), "'it\\'s'");
+    }
+}
@@ -0,0 +1,70 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use iced::{
+    Element, Length,
+    widget::{button, column, text},
+};
+
+use crate::messages::Message;
+
+const MAX_RECENT_FILES: usize = 10;
+
+/// Recently opened or saved file, as shown in the menu.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentFile(pub PathBuf);
+
+impl Display for RecentFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.0.file_name().unwrap_or(self.0.as_os_str()).to_string_lossy();
+        match self.0.parent() {
+            Some(parent) => write!(f, "{} ({})", name, parent.display()),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Moves `path` to the front, dropping the oldest entries past the limit.
+pub fn remember(recent_files: &mut Vec<PathBuf>, path: &Path) {
+    forget(recent_files, path);
+    recent_files.insert(0, path.to_path_buf());
+    recent_files.truncate(MAX_RECENT_FILES);
+}
+
+pub fn forget(recent_files: &mut Vec<PathBuf>, path: &Path) {
+    recent_files.retain(|recent| recent != path);
+}
+
+pub fn menu_items(recent_files: &[PathBuf]) -> Vec<RecentFile> {
+    recent_files.iter().cloned().map(RecentFile).collect()
+}
+
+/// List opened with the shortcut, above the editor.
+pub fn view(recent_files: &[PathBuf]) -> Element<'_, Message> {
+    if recent_files.is_empty() {
+        return text("No recent files").size(12).into();
+    }
+    let entries = menu_items(recent_files).into_iter().map(|file| {
+        button(text(file.to_string()).size(12))
+            .style(button::text)
+            .width(Length::Fill)
+            .on_press(Message::LoadFileSelected(Some(file.0)))
+            .into()
+    });
+    column(entries).into()
+}
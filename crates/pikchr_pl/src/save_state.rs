@@ -1,10 +1,14 @@
 use std::path::PathBuf;
 
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde::{Serialize, de::DeserializeOwned};
 use thiserror::Error;
 use directories::ProjectDirs;
 
-use crate::editor_state::{Editor, EditorSaveState};
+use crate::{
+    document::Document,
+    editor_state::{Editor, EditorSaveState, OpenFile, SaveStateV0},
+    preview,
+};
 
 #[derive(Error, Debug)]
 pub enum SaveStateError {
@@ -16,25 +20,34 @@ pub enum SaveStateError {
   IoError(#[from] std::io::Error),
   #[error("Deserialization error: {0:?}")]
   DeserializationError(#[from] postcard::Error),
+  #[error("Save state version {0} is unknown")]
+  UnknownVersion(u32),
 }
+
+/// Starts every versioned save state. Unversioned ones from before start
+/// with an `Option` tag instead, 0 or 1.
+const MAGIC: [u8; 4] = *b"PKPL";
+
 pub trait Stateful {
     type SaveStateType: Serialize + DeserializeOwned + Clone;
+    /// Bumped on every change to `SaveStateType`, postcard isn't
+    /// self-describing so older state can't be read as the new one.
+    const VERSION: u32;
+
+    /// Reads state saved with an older `VERSION`, 0 being the unversioned
+    /// one.
+    fn migrate(version: u32, bytes: &[u8]) -> Result<Self::SaveStateType, SaveStateError>;
 
 		fn save_write(save_data: Self::SaveStateType) -> Result<(), SaveStateError> {
         let cache_file = get_cache_project_file()?;
 
-        let serialized = postcard::to_stdvec(&save_data)?;
+        let serialized = encode(Self::VERSION, &save_data)?;
         std::fs::write(cache_file, serialized)?;
         Ok(())
 		}
 		fn to_save_state(&self) -> Self::SaveStateType;
-		fn from_save_state(&mut self, state: Self::SaveStateType) -> &Self;
-
-    fn state_save(&self) -> Result<(), SaveStateError> {
-        let save_data = self.to_save_state();
-        Self::save_write(save_data)
+		fn apply_save_state(&mut self, state: Self::SaveStateType) -> &Self;
 
-    }
     fn state_load(&mut self) -> Result<(),SaveStateError> {
         let cache_file = get_cache_project_file()?;
         if !cache_file.exists() {
@@ -42,12 +55,25 @@ pub trait Stateful {
         }
 
         let serialized_byte = std::fs::read(cache_file)?;
-        let deserialized: Self::SaveStateType = postcard::from_bytes::<Self::SaveStateType>(serialized_byte.as_slice())?;
-        self.from_save_state(deserialized);
+        let deserialized = decode::<Self>(serialized_byte.as_slice())?;
+        self.apply_save_state(deserialized);
         Ok(())
     }
 }
 
+fn encode<T: Serialize>(version: u32, state: &T) -> Result<Vec<u8>, postcard::Error> {
+    postcard::to_stdvec(&(MAGIC, version, state))
+}
+
+/// State of the current version, or migrated from the one it was saved with.
+fn decode<S: Stateful + ?Sized>(bytes: &[u8]) -> Result<S::SaveStateType, SaveStateError> {
+    match postcard::take_from_bytes::<([u8; 4], u32)>(bytes) {
+        Ok(((MAGIC, version), rest)) if version == S::VERSION => Ok(postcard::from_bytes(rest)?),
+        Ok(((MAGIC, version), rest)) => S::migrate(version, rest),
+        _ => S::migrate(0, bytes),
+    }
+}
+
 fn get_cache_project_file() -> Result<PathBuf, SaveStateError> {
     let project_dir = ProjectDirs::from("sh", "axk", "pikchr_pl").ok_or(SaveStateError::ProjectDirNotFound)?;
     let cache_dir = project_dir.cache_dir();
@@ -60,22 +86,123 @@ fn get_cache_project_file() -> Result<PathBuf, SaveStateError> {
 
 impl Stateful for Editor {
     type SaveStateType = EditorSaveState;
+    const VERSION: u32 = 1;
+
+    fn migrate(version: u32, bytes: &[u8]) -> Result<EditorSaveState, SaveStateError> {
+        match version {
+            0 => Ok(postcard::from_bytes::<SaveStateV0>(bytes)?.into()),
+            _ => Err(SaveStateError::UnknownVersion(version)),
+        }
+    }
 
     fn to_save_state(&self) -> Self::SaveStateType {
+        let open_files: Vec<(usize, OpenFile)> = self
+            .documents
+            .iter()
+            .enumerate()
+            .filter_map(|(index, document)| {
+                let path = document.current_file.clone()?;
+                Some((index, OpenFile {
+                    path,
+                    operating_mode: document.operating_mode,
+                    preview_zoom: document.preview_view.zoom,
+                }))
+            })
+            .collect();
         EditorSaveState {
-            current_file: self.current_file.clone(),
+            active_file: open_files.iter().position(|(index, _)| *index == self.active),
+            open_files: open_files.into_iter().map(|(_, file)| file).collect(),
             file_watch_mode: self.file_watch_mode,
             show_debug: self.show_debug,
-            operating_mode: self.operating_mode,
+            operating_mode: self.doc().operating_mode,
+            recent_files: self.recent_files.clone(),
+            disabled_modules: self.disabled_modules.clone(),
+            configured_modules_dir: self.configured_modules_dir.clone(),
         }
     }
 
-    fn from_save_state(&mut self, state: Self::SaveStateType) -> &Self {
-            self.current_file = state.current_file;
+    /// Reopens files that are still there, the rest is dropped silently.
+    fn apply_save_state(&mut self, state: Self::SaveStateType) -> &Self {
             self.file_watch_mode = state.file_watch_mode;
             self.show_debug = state.show_debug;
-            self.operating_mode = state.operating_mode;
+            self.doc_mut().operating_mode = state.operating_mode;
+            self.recent_files = state.recent_files;
+            self.disabled_modules = state.disabled_modules;
+            self.configured_modules_dir = state.configured_modules_dir;
+
+            let mut active = None;
+            for (index, file) in state.open_files.iter().enumerate() {
+                let id = self.next_document_id();
+                let Ok(mut document) = Document::open(id, &file.path, file.operating_mode) else {
+                    continue;
+                };
+                document.preview_view = preview::View::with_zoom(file.preview_zoom);
+                self.push_document(document);
+                self.load_modules(self.active);
+                if state.active_file == Some(index) {
+                    active = Some(self.active);
+                }
+            }
+            if let Some(active) = active {
+                self.active = active;
+            }
             self
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperatingMode;
+
+    fn state() -> EditorSaveState {
+        EditorSaveState {
+            open_files: vec![OpenFile {
+                path: PathBuf::from("diagram.pl"),
+                operating_mode: OperatingMode::PrologMode,
+                preview_zoom: Some(1.5),
+            }],
+            active_file: Some(0),
+            file_watch_mode: true,
+            show_debug: false,
+            operating_mode: OperatingMode::PrologMode,
+            recent_files: vec![PathBuf::from("diagram.pl")],
+            disabled_modules: Default::default(),
+            configured_modules_dir: None,
+        }
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let bytes = encode(Editor::VERSION, &state()).unwrap();
+        assert_eq!(decode::<Editor>(&bytes).unwrap(), state());
+    }
+
+    #[test]
+    fn unversioned_state_is_migrated() {
+        let legacy = SaveStateV0 {
+            current_file: Some(PathBuf::from("diagram.pl")),
+            file_watch_mode: true,
+            show_debug: false,
+            operating_mode: OperatingMode::PrologMode,
+        };
+        let bytes = postcard::to_stdvec(&legacy).unwrap();
+        let expected = EditorSaveState {
+            open_files: vec![OpenFile {
+                preview_zoom: None,
+                ..state().open_files[0].clone()
+            }],
+            ..state()
+        };
+        assert_eq!(decode::<Editor>(&bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn unknown_version_is_reported() {
+        let bytes = encode(Editor::VERSION + 1, &state()).unwrap();
+        assert!(matches!(
+            decode::<Editor>(&bytes),
+            Err(SaveStateError::UnknownVersion(version)) if version == Editor::VERSION + 1
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+/// Best guess of the Prolog line that produced `pikchr_line`.
+///
+/// DCGs don't keep track of what they emitted, so this looks for the
+/// statements verbatim first (heredocs, string literals) and then for
+/// their quoted labels, e.g. `box "Hello"` is found through `box("Hello")`.
+pub fn prolog_line(pikchr_line: &str, prolog_source: &str) -> Option<usize> {
+    let statements: Vec<&str> = pikchr_line
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    statements
+        .iter()
+        // Single words like `box` or `move` are all over any source
+        .filter(|s| s.contains(char::is_whitespace))
+        .find_map(|s| find_line(prolog_source, s))
+        .or_else(|| {
+            statements
+                .iter()
+                .flat_map(|s| labels(s))
+                .find_map(|label| find_line(prolog_source, &format!("\"{}\"", label)))
+        })
+}
+
+fn labels(statement: &str) -> impl Iterator<Item = &str> {
+    statement
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|s| !s.is_empty())
+}
+
+fn find_line(source: &str, needle: &str) -> Option<usize> {
+    source
+        .lines()
+        .position(|line| !line.trim_start().starts_with('%') && line.contains(needle))
+}
@@ -11,123 +11,168 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{iter::Peekable, ops::Range, str::CharIndices};
+use std::{fmt, ops::Range};
 
 use iced::{
     Color, Font,
-    advanced::text::{Renderer, highlighter::Format},
-    highlighter::{self, Theme},
+    advanced::text::highlighter::Format,
+    font::{Style, Weight},
+    theme::Palette,
     widget::{text::Highlighter, text_editor::Catalog},
 };
 
+/// Where a line starts, heredocs and block comments span lines.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     Idle,
+    /// Inside `===` heredoc
     InTripleQuotes,
+    /// Inside `/* */`
+    InBlockComment,
 }
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     String,
     Heredoc,
+    QuotedAtom,
+    /// `\n` and doubled quotes in quoted text
+    Escape,
+    Comment,
+    Variable,
+    Number,
+    /// `0'c` character code
+    Char,
+    /// `txt:` of `txt:text_outer`
+    Module,
     Operator,
     HighlightOperator,
     RiskyOperator,
     Dot,
-    Text,
+    Error,
+}
+
+/// Colours of tokens, taken from the editor's theme.
+#[derive(Debug, Clone, Copy)]
+pub struct Colors {
+    pub string:    Color,
+    pub heredoc:   Color,
+    pub atom:      Color,
+    pub escape:    Color,
+    pub comment:   Color,
+    pub variable:  Color,
+    pub number:    Color,
+    pub module:    Color,
+    pub operator:  Color,
+    pub keyword:   Color,
+    pub risky:     Color,
+    pub label:     Color,
+    pub direction: Color,
+}
+
+impl Colors {
+    pub fn from_palette(palette: &Palette) -> Self {
+        Self {
+            string:    palette.success,
+            heredoc:   palette.warning,
+            atom:      mix(palette.success, palette.text, 0.4),
+            escape:    palette.warning,
+            comment:   palette.text.scale_alpha(0.5),
+            variable:  mix(palette.warning, palette.danger, 0.5),
+            number:    mix(palette.primary, palette.success, 0.5),
+            module:    mix(palette.primary, palette.text, 0.3),
+            operator:  mix(palette.primary, palette.danger, 0.5),
+            keyword:   palette.primary,
+            risky:     palette.danger,
+            label:     mix(palette.warning, palette.text, 0.3),
+            direction: mix(palette.primary, palette.warning, 0.5),
+        }
+    }
+}
+
+/// `a` with `amount` of `b`.
+fn mix(a: Color, b: Color, amount: f32) -> Color {
+    Color {
+        r: a.r + (b.r - a.r) * amount,
+        g: a.g + (b.g - a.g) * amount,
+        b: a.b + (b.b - a.b) * amount,
+        a: a.a + (b.a - a.a) * amount,
+    }
 }
 
 pub struct PrologHighlighter {
-    current_line: usize
+    current_line: usize,
+    error_line:   Option<usize>,
+    /// State at the start of each line highlighted so far
+    states:       Vec<State>,
 }
 
 impl PrologHighlighter {
     pub fn colorize(token: &Token, theme: &impl Catalog) -> Format<Font> {
-        let palette = theme.palette().unwrap();
-        //let string_color = Color::from_rgb(0.2, 0.7, 0.2);
-        let string_color = Color::from_rgb8(133,200,97);
-        let operator_color = Color::from_rgb(0.7, 0.0, 1.0);
-        let risky_color = Color::from_rgb8(233,51,58);
-        let heredoc_color =Color::from_rgb8(238,253,84);
-        match token {
-            Token::String => Format {
-                color: Some(string_color),
-                font: None,
-            },
-            Token::RiskyOperator => Format {
-                color: Some(risky_color),
-                font: Some(Font::MONOSPACE),
-            },
-            Token::HighlightOperator => Format {
-                color: Some(palette.primary),
-                font: Some(Font::MONOSPACE),
-            },
-            Token::Dot => Format {
-                color: Some(palette.primary),
-                font: Some(Font::MONOSPACE),
+        let palette = theme.palette().unwrap_or(Palette::DARK);
+        let colors = Colors::from_palette(&palette);
+        let (color, font) = match token {
+            Token::String => (colors.string, None),
+            Token::Heredoc => (colors.heredoc, Some(Font::MONOSPACE)),
+            Token::QuotedAtom => (colors.atom, None),
+            Token::Escape => (colors.escape, None),
+            Token::Comment => {
+                let italic = Font {
+                    style: Style::Italic,
+                    ..Font::MONOSPACE
+                };
+                (colors.comment, Some(italic))
             },
-            Token::Operator => Format {
-                color: Some(operator_color),
-                font: Some(Font::MONOSPACE),
-            },
-            Token::Heredoc => Format {
-                color: Some(heredoc_color),
-                font: Some(Font::MONOSPACE),
-            },
-            Token::Text => Format::default(),
+            Token::Variable => (colors.variable, None),
+            Token::Number | Token::Char => (colors.number, None),
+            Token::Module => (colors.module, None),
+            Token::Operator => (colors.operator, Some(Font::MONOSPACE)),
+            Token::HighlightOperator | Token::Dot => (colors.keyword, Some(Font::MONOSPACE)),
+            Token::RiskyOperator | Token::Error => (colors.risky, Some(Font::MONOSPACE)),
+        };
+        Format {
+            color: Some(color),
+            font,
         }
     }
 }
 
 impl Highlighter for PrologHighlighter {
-    type Settings = ();
+    /// Line with the last render error
+    type Settings = Option<usize>;
 
     type Highlight = Token;
     type Iterator<'a> = Box<dyn Iterator<Item = (Range<usize>, Self::Highlight)> + 'a>;
 
-    fn new(_settings: &Self::Settings) -> Self {
+    fn new(settings: &Self::Settings) -> Self {
         Self {
-            current_line: 0
+            current_line: 0,
+            error_line:   *settings,
+            states:       vec![State::Idle],
         }
     }
 
-    fn update(&mut self, _new_settings: &Self::Settings) {}
+    fn update(&mut self, new_settings: &Self::Settings) {
+        self.error_line = *new_settings;
+    }
     fn change_line(&mut self, line: usize) {
         self.current_line = line;
+        self.states.truncate(line + 1);
     }
 
     fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
-        let mut highlights = Vec::new();
-        let mut chars = line.char_indices().peekable();
-
-        while let Some((i, _)) = chars.peek().cloned() {
-            let mut it = line[i..].chars();
-            let c1 = it.next().unwrap_or('\0');
-            let c2 = it.next().unwrap_or('\0');
-            let c3 = it.next().unwrap_or('\0');
-            let check_chars = (c1, c2, c3);
-            match check_chars {
-                ('=', '=', '=') => {
-                    handle_operator(i, &mut chars, &mut highlights, 3, Token::Heredoc)
-                },
-                ('-', '>', _) => {
-                    handle_operator(i, &mut chars, &mut highlights, 2, Token::RiskyOperator)
-                },
-                ('-', '-', '>') => {
-                    handle_operator(i, &mut chars, &mut highlights, 3, Token::HighlightOperator)
-                },
-                (':', '-', _) => {
-                    handle_operator(i, &mut chars, &mut highlights, 2, Token::Operator)
-                },
-                ('"', _, _) => parse_string(i, &mut chars, &mut highlights),
-                (c, _, _) if matches!(c, '.' | ',' | ';' | '=' | '!') => {
-                    categorize_single_char_token(i, c, &mut chars, &mut highlights)
-                },
-                _ => {
-                    chars.next();
-                },
-            }
-        }
+        let line_index = self.current_line;
+        self.current_line += 1;
 
+        let state = self.states.get(line_index).copied().unwrap_or(State::Idle);
+        let (highlights, next) = tokenize(line, state);
+        self.states.resize(line_index + 1, State::Idle);
+        self.states.push(next);
+
+        // Still tokenized, the next line depends on it
+        if self.error_line == Some(line_index) {
+            return Box::new(std::iter::once((0..line.len(), Token::Error)));
+        }
         Box::new(highlights.into_iter())
     }
 
@@ -136,47 +181,819 @@ impl Highlighter for PrologHighlighter {
     }
 }
 
-fn parse_string(
-    i: usize,
-    chars: &mut Peekable<CharIndices>,
-    highlights: &mut Vec<(Range<usize>, Token)>,
-) {
-    let start = i;
-    chars.next();
-    let mut end = i + 1;
-    while let Some((j, next_c)) = chars.next() {
-        end = j + 1;
-        if next_c == '"' {
+/// Characters of operators like `:-`, `=..` or `\+`.
+fn is_symbol(c: char) -> bool {
+    "+-*/\\^<>=~:.?@#&$".contains(c)
+}
+
+fn is_alphanumeric(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// End of the run of characters from `start` matching `matches`.
+fn run(line: &str, start: usize, matches: impl Fn(char) -> bool) -> usize {
+    line[start..]
+        .find(|c: char| !matches(c))
+        .map_or(line.len(), |length| start + length)
+}
+
+/// Highlights of `line` starting in `state`, and the state the next line
+/// starts in.
+fn tokenize(line: &str, mut state: State) -> (Vec<(Range<usize>, Token)>, State) {
+    let mut highlights = vec![];
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        match state {
+            State::InTripleQuotes => {
+                let end = rest.find("===").map_or(line.len(), |end| {
+                    state = State::Idle;
+                    i + end + 3
+                });
+                highlights.push((i..end, Token::Heredoc));
+                i = end;
+                continue;
+            },
+            State::InBlockComment => {
+                let end = rest.find("*/").map_or(line.len(), |end| {
+                    state = State::Idle;
+                    i + end + 2
+                });
+                highlights.push((i..end, Token::Comment));
+                i = end;
+                continue;
+            },
+            State::Idle => (),
+        }
+
+        let Some(c) = rest.chars().next() else {
             break;
+        };
+        if rest.starts_with("===") {
+            // Opening delimiter goes with the contents
+            state = State::InTripleQuotes;
+            highlights.push((i..i + 3, Token::Heredoc));
+            i += 3;
+            continue;
+        }
+        if rest.starts_with("/*") {
+            state = State::InBlockComment;
+            highlights.push((i..i + 2, Token::Comment));
+            i += 2;
+            continue;
         }
+        let end = match c {
+            '%' => {
+                highlights.push((i..line.len(), Token::Comment));
+                line.len()
+            },
+            '"' | '`' => quoted(line, i, c, Token::String, &mut highlights),
+            '\'' => quoted(line, i, c, Token::QuotedAtom, &mut highlights),
+            '0' if rest.starts_with("0'") => {
+                let end = character_code(line, i);
+                highlights.push((i..end, Token::Char));
+                end
+            },
+            c if c.is_ascii_digit() => {
+                let end = number(line, i);
+                highlights.push((i..end, Token::Number));
+                end
+            },
+            c if c.is_uppercase() || c == '_' => {
+                let end = run(line, i, is_alphanumeric);
+                highlights.push((i..end, Token::Variable));
+                end
+            },
+            c if c.is_alphabetic() => {
+                let end = run(line, i, is_alphanumeric);
+                let after = &line[end..];
+                // `module:name`, not `head:-` or `a::b`
+                let qualified = after.strip_prefix(':').is_some_and(|name| {
+                    name.starts_with(|c: char| c.is_lowercase() || c == '\'')
+                });
+                if qualified {
+                    highlights.push((i..end + 1, Token::Module));
+                    end + 1
+                } else {
+                    end
+                }
+            },
+            ',' | ';' | '!' | '|' => {
+                highlights.push((i..i + 1, Token::Operator));
+                i + 1
+            },
+            c if is_symbol(c) => {
+                let end = run(line, i, is_symbol);
+                let token = match &line[i..end] {
+                    "-->" => Token::HighlightOperator,
+                    "->" => Token::RiskyOperator,
+                    "." => Token::Dot,
+                    _ => Token::Operator,
+                };
+                highlights.push((i..end, token));
+                end
+            },
+            c => i + c.len_utf8(),
+        };
+        i = end;
     }
-    highlights.push((start..end, Token::String));
+    (highlights, state)
 }
-fn categorize_single_char_token(
-    i: usize,
-    c: char,
-    chars: &mut Peekable<CharIndices>,
+
+/// Quoted text from the opening quote at `start`, escapes split out. Ends
+/// with the line if the quote isn't closed.
+fn quoted(
+    line: &str,
+    start: usize,
+    quote: char,
+    token: Token,
     highlights: &mut Vec<(Range<usize>, Token)>,
-) {
-    let token = match c {
-        '.' => Token::Dot,
-        ',' | ';' | '=' | '!' => Token::Operator,
-        _ => unreachable!(),
+) -> usize {
+    let mut segment = start;
+    let mut chars = line[start + 1..].char_indices().map(|(i, c)| (start + 1 + i, c)).peekable();
+    while let Some((i, c)) = chars.next() {
+        let escape_end = if c == '\\' {
+            chars.next().map_or(line.len(), |(j, escaped)| {
+                // `\x41\` and `\101\` run up to a closing backslash
+                if escaped == 'x' || escaped.is_digit(8) {
+                    let end = run(line, j + 1, |c| c.is_ascii_hexdigit());
+                    if line[end..].starts_with('\\') { end + 1 } else { end }
+                } else {
+                    j + escaped.len_utf8()
+                }
+            })
+        } else if c == quote {
+            if chars.next_if(|&(_, next)| next == quote).is_none() {
+                highlights.push((segment..i + 1, token));
+                return i + 1;
+            }
+            i + 2
+        } else {
+            continue;
+        };
+        if segment < i {
+            highlights.push((segment..i, token.clone()));
+        }
+        highlights.push((i..escape_end, Token::Escape));
+        segment = escape_end;
+        while chars.next_if(|&(j, _)| j < escape_end).is_some() {}
+    }
+    if segment < line.len() {
+        highlights.push((segment..line.len(), token));
+    }
+    line.len()
+}
+
+/// End of `0'c`, `0'\n` or `0'''` at `start`.
+fn character_code(line: &str, start: usize) -> usize {
+    let code = start + 2;
+    let mut chars = line[code..].chars();
+    let length = match (chars.next(), chars.next()) {
+        (Some('\\'), Some(escaped)) => 1 + escaped.len_utf8(),
+        (Some('\''), Some('\'')) => 2,
+        (Some(c), _) => c.len_utf8(),
+        (None, _) => 0,
     };
-    let end = i + 1;
-    chars.next();
-    highlights.push((i..end, token));
+    code + length
 }
 
-fn handle_operator(
-    i: usize,
-    chars: &mut Peekable<CharIndices>,
-    highlights: &mut Vec<(Range<usize>, Token)>,
-    operator_length: usize,
-    token: Token,
-) {
-    highlights.push((i..i + operator_length, token));
-    for _ in 0..operator_length {
-        chars.next();
+/// End of a number at `start`: `42`, `0x2A`, `1.5e3`.
+fn number(line: &str, start: usize) -> usize {
+    let rest = &line[start..];
+    if rest.len() > 2 && rest.starts_with('0') && rest[1..].starts_with(['x', 'o', 'b']) {
+        return run(line, start + 2, |c| c.is_ascii_hexdigit());
+    }
+    let mut end = run(line, start, |c| c.is_ascii_digit());
+    // A dot is only a decimal point with digits after it
+    if line[end..].starts_with('.')
+        && line[end + 1..].starts_with(|c: char| c.is_ascii_digit())
+    {
+        end = run(line, end + 1, |c| c.is_ascii_digit());
+        let exponent = line[end..]
+            .strip_prefix(['e', 'E'])
+            .map(|exponent| exponent.strip_prefix(['+', '-']).unwrap_or(exponent));
+        if exponent.is_some_and(|digits| digits.starts_with(|c: char| c.is_ascii_digit())) {
+            let digits = line.len() - exponent.map_or(0, str::len);
+            end = run(line, digits, |c| c.is_ascii_digit());
+        }
+    }
+    end
+}
+
+/// What a Pikchr keyword is, shown next to completions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    Object,
+    Attribute,
+    Direction,
+    Path,
+    Text,
+    Position,
+    Statement,
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Keyword::Object => "object",
+            Keyword::Attribute => "attribute",
+            Keyword::Direction => "direction",
+            Keyword::Path => "path",
+            Keyword::Text => "text",
+            Keyword::Position => "position",
+            Keyword::Statement => "statement",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Keywords of Pikchr, with what kind they are.
+pub const PIKCHR_KEYWORDS: &[(&str, Keyword)] = &[
+    ("arc", Keyword::Object),
+    ("arrow", Keyword::Object),
+    ("box", Keyword::Object),
+    ("circle", Keyword::Object),
+    ("cylinder", Keyword::Object),
+    ("diamond", Keyword::Object),
+    ("dot", Keyword::Object),
+    ("ellipse", Keyword::Object),
+    ("file", Keyword::Object),
+    ("line", Keyword::Object),
+    ("move", Keyword::Object),
+    ("oval", Keyword::Object),
+    ("spline", Keyword::Object),
+    ("text", Keyword::Object),
+    ("behind", Keyword::Attribute),
+    ("ccw", Keyword::Attribute),
+    ("chop", Keyword::Attribute),
+    ("close", Keyword::Attribute),
+    ("color", Keyword::Attribute),
+    ("cw", Keyword::Attribute),
+    ("dashed", Keyword::Attribute),
+    ("diameter", Keyword::Attribute),
+    ("dotted", Keyword::Attribute),
+    ("fill", Keyword::Attribute),
+    ("fit", Keyword::Attribute),
+    ("height", Keyword::Attribute),
+    ("ht", Keyword::Attribute),
+    ("invis", Keyword::Attribute),
+    ("invisible", Keyword::Attribute),
+    ("rad", Keyword::Attribute),
+    ("radius", Keyword::Attribute),
+    ("same", Keyword::Attribute),
+    ("solid", Keyword::Attribute),
+    ("thick", Keyword::Attribute),
+    ("thickness", Keyword::Attribute),
+    ("thin", Keyword::Attribute),
+    ("wid", Keyword::Attribute),
+    ("width", Keyword::Attribute),
+    ("down", Keyword::Direction),
+    ("left", Keyword::Direction),
+    ("right", Keyword::Direction),
+    ("up", Keyword::Direction),
+    ("at", Keyword::Path),
+    ("even", Keyword::Path),
+    ("from", Keyword::Path),
+    ("go", Keyword::Path),
+    ("heading", Keyword::Path),
+    ("then", Keyword::Path),
+    ("to", Keyword::Path),
+    ("until", Keyword::Path),
+    ("with", Keyword::Path),
+    ("above", Keyword::Text),
+    ("aligned", Keyword::Text),
+    ("below", Keyword::Text),
+    ("big", Keyword::Text),
+    ("bold", Keyword::Text),
+    ("center", Keyword::Text),
+    ("italic", Keyword::Text),
+    ("ljust", Keyword::Text),
+    ("mono", Keyword::Text),
+    ("monospace", Keyword::Text),
+    ("rjust", Keyword::Text),
+    ("small", Keyword::Text),
+    ("bottom", Keyword::Position),
+    ("east", Keyword::Position),
+    ("end", Keyword::Position),
+    ("first", Keyword::Position),
+    ("last", Keyword::Position),
+    ("north", Keyword::Position),
+    ("of", Keyword::Position),
+    ("previous", Keyword::Position),
+    ("south", Keyword::Position),
+    ("start", Keyword::Position),
+    ("top", Keyword::Position),
+    ("way", Keyword::Position),
+    ("west", Keyword::Position),
+    ("assert", Keyword::Statement),
+    ("define", Keyword::Statement),
+    ("print", Keyword::Statement),
+];
+
+/// Places of an object after a dot, as in `A.ne` or `last box.start`.
+const PIKCHR_EDGES: &[&str] = &[
+    "n", "ne", "e", "se", "s", "sw", "w", "nw", "c", "t", "north", "east", "south", "west",
+    "start", "end", "top", "bottom", "left", "right", "center", "x", "y",
+];
+
+/// Variables Pikchr reads its defaults from.
+const PIKCHR_VARIABLES: &[&str] = &[
+    "arcrad", "arrowhead", "arrowht", "arrowwid", "bottommargin", "boxht", "boxrad", "boxwid",
+    "charht", "charwid", "circlerad", "cylht", "cylrad", "cylwid", "diamondht", "diamondwid",
+    "dotrad", "ellipseht", "ellipsewid", "fileht", "filerad", "filewid", "fontscale",
+    "leftmargin", "lineht", "linewid", "margin", "movewid", "ovalht", "ovalwid", "rightmargin",
+    "scale", "textht", "textwid", "topmargin",
+];
+
+/// Arrowheads, attributes though written as symbols.
+const PIKCHR_ARROWS: &[&str] = &["<->", "->", "<-"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PikchrToken {
+    Keyword(Keyword),
+    /// `A` of `A: box` and of `A.ne`
+    Label,
+    String,
+    /// `\"` and the like in strings
+    Escape,
+    /// With its unit, `2cm` or `50%`, and ordinals like `2nd`
+    Number,
+    Comment,
+    /// Name of a `define`, where defined and where used
+    Macro,
+    /// `$x`, `@x` and the built in defaults like `boxwid`
+    Variable,
+    /// `.ne` of `A.ne`
+    Edge,
+    Arrow,
+    Operator,
+    Error,
+}
+
+pub struct PikchrHighlighter {
+    current_line: usize,
+    error_line:   Option<usize>,
+    /// State at the start of each line highlighted so far, with how many
+    /// `macros` were defined above it
+    states:       Vec<(State, usize)>,
+    /// Names defined so far, used ones are highlighted too
+    macros:       Vec<String>,
+}
+
+impl PikchrHighlighter {
+    pub fn colorize(token: &PikchrToken, theme: &impl Catalog) -> Format<Font> {
+        let palette = theme.palette().unwrap_or(Palette::DARK);
+        let colors = Colors::from_palette(&palette);
+        let color = match token {
+            PikchrToken::Keyword(Keyword::Object | Keyword::Statement) => colors.keyword,
+            PikchrToken::Keyword(Keyword::Attribute | Keyword::Text) | PikchrToken::Arrow => {
+                colors.atom
+            },
+            PikchrToken::Keyword(Keyword::Direction) => colors.direction,
+            PikchrToken::Keyword(Keyword::Path | Keyword::Position) | PikchrToken::Edge => {
+                colors.module
+            },
+            PikchrToken::Label => colors.label,
+            PikchrToken::String => colors.string,
+            PikchrToken::Escape => colors.escape,
+            PikchrToken::Number => colors.number,
+            PikchrToken::Comment => colors.comment,
+            PikchrToken::Macro => colors.heredoc,
+            PikchrToken::Variable => colors.variable,
+            PikchrToken::Operator => colors.operator,
+            PikchrToken::Error => colors.risky,
+        };
+        Format {
+            color: Some(color),
+            font:  Self::font(token),
+        }
+    }
+
+    /// Font of `token`, which doesn't depend on the theme.
+    pub fn font(token: &PikchrToken) -> Option<Font> {
+        match token {
+            PikchrToken::Keyword(Keyword::Object) => {
+                Some(Font {
+                    weight: Weight::Bold,
+                    ..Font::MONOSPACE
+                })
+            },
+            PikchrToken::Comment => {
+                Some(Font {
+                    style: Style::Italic,
+                    ..Font::MONOSPACE
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for PikchrHighlighter {
+    /// Line with the last render error
+    type Settings = Option<usize>;
+
+    type Highlight = PikchrToken;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Self::Highlight)>;
+
+    fn new(settings: &Self::Settings) -> Self {
+        Self {
+            current_line: 0,
+            error_line:   *settings,
+            states:       vec![(State::Idle, 0)],
+            macros:       vec![],
+        }
+    }
+
+    fn update(&mut self, new_settings: &Self::Settings) {
+        self.error_line = *new_settings;
+    }
+    fn change_line(&mut self, line: usize) {
+        self.current_line = line;
+        self.states.truncate(line + 1);
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        let line_index = self.current_line;
+        self.current_line += 1;
+
+        let (state, defined) = self
+            .states
+            .get(line_index)
+            .copied()
+            .unwrap_or((State::Idle, self.macros.len()));
+        // Lines from here on may have changed, their definitions are read
+        // again
+        self.macros.truncate(defined);
+        let (highlights, next) = tokenize_pikchr(line, state, &mut self.macros);
+        self.states.resize(line_index + 1, (State::Idle, defined));
+        self.states.push((next, self.macros.len()));
+
+        if self.error_line == Some(line_index) {
+            return vec![(0..line.len(), PikchrToken::Error)].into_iter();
+        }
+        highlights.into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
+
+fn pikchr_keyword(word: &str) -> Option<Keyword> {
+    PIKCHR_KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == word)
+        .map(|&(_, kind)| kind)
+}
+
+/// Highlights of a line of Pikchr starting in `state`, and the state the
+/// next line starts in. Names after `define` are added to `macros`.
+fn tokenize_pikchr(
+    line: &str,
+    mut state: State,
+    macros: &mut Vec<String>,
+) -> (Vec<(Range<usize>, PikchrToken)>, State) {
+    let mut highlights = vec![];
+    // The next name is being defined
+    let mut defining = false;
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        if state == State::InBlockComment {
+            let end = rest.find("*/").map_or(line.len(), |end| {
+                state = State::Idle;
+                i + end + 2
+            });
+            highlights.push((i..end, PikchrToken::Comment));
+            i = end;
+            continue;
+        }
+
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        if rest.starts_with("/*") {
+            state = State::InBlockComment;
+            highlights.push((i..i + 2, PikchrToken::Comment));
+            i += 2;
+            continue;
+        }
+        if let Some(arrow) = PIKCHR_ARROWS.iter().find(|arrow| rest.starts_with(**arrow)) {
+            highlights.push((i..i + arrow.len(), PikchrToken::Arrow));
+            i += arrow.len();
+            continue;
+        }
+        let after = &rest[c.len_utf8()..];
+        let end = match c {
+            '#' => {
+                highlights.push((i..line.len(), PikchrToken::Comment));
+                line.len()
+            },
+            '/' if after.starts_with('/') => {
+                highlights.push((i..line.len(), PikchrToken::Comment));
+                line.len()
+            },
+            '"' => pikchr_string(line, i, &mut highlights),
+            c if c.is_ascii_digit()
+                || (c == '.' && after.starts_with(|c: char| c.is_ascii_digit())) =>
+            {
+                let end = pikchr_number(line, i);
+                highlights.push((i..end, PikchrToken::Number));
+                end
+            },
+            '.' if after.starts_with(char::is_alphabetic) => {
+                let end = run(line, i + 1, is_alphanumeric);
+                if PIKCHR_EDGES.contains(&&line[i + 1..end]) {
+                    highlights.push((i..end, PikchrToken::Edge));
+                    end
+                } else {
+                    // `.wid` and the like are keywords
+                    i + 1
+                }
+            },
+            '$' | '@' if after.starts_with(is_alphanumeric) => {
+                let end = run(line, i + 1, is_alphanumeric);
+                highlights.push((i..end, PikchrToken::Variable));
+                end
+            },
+            c if c.is_uppercase() => {
+                let end = run(line, i, is_alphanumeric);
+                // `A:` places a label, `A := 1` is an assignment
+                let end = if line[end..].starts_with(':') && !line[end..].starts_with(":=") {
+                    end + 1
+                } else {
+                    end
+                };
+                highlights.push((i..end, PikchrToken::Label));
+                end
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let end = run(line, i, is_alphanumeric);
+                let word = &line[i..end];
+                let token = if defining {
+                    defining = false;
+                    if !macros.iter().any(|name| name == word) {
+                        macros.push(word.to_string());
+                    }
+                    Some(PikchrToken::Macro)
+                } else if let Some(kind) = pikchr_keyword(word) {
+                    defining = word == "define";
+                    Some(PikchrToken::Keyword(kind))
+                } else if macros.iter().any(|name| name == word) {
+                    Some(PikchrToken::Macro)
+                } else if PIKCHR_VARIABLES.contains(&word) {
+                    Some(PikchrToken::Variable)
+                } else {
+                    None
+                };
+                if let Some(token) = token {
+                    highlights.push((i..end, token));
+                }
+                end
+            },
+            c if "+-*/=<>!".contains(c) => {
+                let end = run(line, i, |c| "+-*/=<>!".contains(c));
+                highlights.push((i..end, PikchrToken::Operator));
+                end
+            },
+            c => i + c.len_utf8(),
+        };
+        i = end;
+    }
+    (highlights, state)
+}
+
+/// A string from the quote at `start`, escapes split out. Ends with the
+/// line if the quote isn't closed.
+fn pikchr_string(
+    line: &str,
+    start: usize,
+    highlights: &mut Vec<(Range<usize>, PikchrToken)>,
+) -> usize {
+    let mut segment = start;
+    let mut chars = line[start + 1..].char_indices().map(|(i, c)| (start + 1 + i, c));
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                highlights.push((segment..i + 1, PikchrToken::String));
+                return i + 1;
+            },
+            '\\' => {
+                let end = chars.next().map_or(line.len(), |(j, escaped)| j + escaped.len_utf8());
+                if segment < i {
+                    highlights.push((segment..i, PikchrToken::String));
+                }
+                highlights.push((i..end, PikchrToken::Escape));
+                segment = end;
+            },
+            _ => (),
+        }
+    }
+    if segment < line.len() {
+        highlights.push((segment..line.len(), PikchrToken::String));
+    }
+    line.len()
+}
+
+/// End of a number at `start`, with its unit: `0.5`, `2cm`, `50%`, `0xff`.
+fn pikchr_number(line: &str, start: usize) -> usize {
+    if line[start..].starts_with("0x") {
+        return run(line, start + 2, |c| c.is_ascii_hexdigit());
+    }
+    let end = run(line, start, |c| c.is_ascii_digit() || c == '.');
+    if line[end..].starts_with('%') {
+        end + 1
+    } else {
+        run(line, end, |c| c.is_ascii_alphabetic())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Highlighted text of `line` with its token, and the next state.
+    fn highlighted(line: &str, state: State) -> (Vec<(&str, Token)>, State) {
+        let (highlights, next) = tokenize(line, state);
+        let tokens = highlights
+            .into_iter()
+            .map(|(range, token)| (&line[range], token))
+            .collect();
+        (tokens, next)
+    }
+
+    #[test]
+    fn line_comment_runs_to_the_end() {
+        let (tokens, next) = highlighted("a. % b 'c'", State::Idle);
+        assert_eq!(tokens, [(".", Token::Dot), ("% b 'c'", Token::Comment)]);
+        assert_eq!(next, State::Idle);
+    }
+
+    #[test]
+    fn block_comment_spans_lines() {
+        let (tokens, next) = highlighted("a /* b", State::Idle);
+        assert_eq!(tokens, [("/*", Token::Comment), (" b", Token::Comment)]);
+        assert_eq!(next, State::InBlockComment);
+
+        let (tokens, next) = highlighted("c", State::InBlockComment);
+        assert_eq!(tokens, [("c", Token::Comment)]);
+        assert_eq!(next, State::InBlockComment);
+
+        let (tokens, next) = highlighted("d */ X", State::InBlockComment);
+        assert_eq!(tokens, [("d */", Token::Comment), ("X", Token::Variable)]);
+        assert_eq!(next, State::Idle);
+    }
+
+    #[test]
+    fn heredoc_spans_lines() {
+        let (tokens, next) = highlighted("label(===a 'b", State::Idle);
+        assert_eq!(tokens, [("===", Token::Heredoc), ("a 'b", Token::Heredoc)]);
+        assert_eq!(next, State::InTripleQuotes);
+
+        let (tokens, next) = highlighted("% c", State::InTripleQuotes);
+        assert_eq!(tokens, [("% c", Token::Heredoc)]);
+        assert_eq!(next, State::InTripleQuotes);
+
+        let (tokens, next) = highlighted("d===).", State::InTripleQuotes);
+        assert_eq!(tokens, [("d===", Token::Heredoc), (".", Token::Dot)]);
+        assert_eq!(next, State::Idle);
+    }
+
+    #[test]
+    fn character_codes() {
+        let (tokens, _) = highlighted("0'a 0'\\n 0''' 0' ", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("0'a", Token::Char),
+                ("0'\\n", Token::Char),
+                ("0'''", Token::Char),
+                ("0' ", Token::Char),
+            ]
+        );
+        assert_eq!(character_code("0'", 0), 2);
+    }
+
+    #[test]
+    fn escapes_in_quoted_text() {
+        let (tokens, _) = highlighted(r#""a\nb""#, State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("\"a", Token::String),
+                ("\\n", Token::Escape),
+                ("b\"", Token::String),
+            ]
+        );
+
+        let (tokens, _) = highlighted(r"'it''s' '\x41\'", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("'it", Token::QuotedAtom),
+                ("''", Token::Escape),
+                ("s'", Token::QuotedAtom),
+                ("'", Token::QuotedAtom),
+                ("\\x41\\", Token::Escape),
+                ("'", Token::QuotedAtom),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_atoms_hide_what_they_quote() {
+        let (tokens, _) = highlighted("'A % b'. `c`", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("'A % b'", Token::QuotedAtom),
+                (".", Token::Dot),
+                ("`c`", Token::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_quote_ends_with_the_line() {
+        let (tokens, next) = highlighted("\"abc", State::Idle);
+        assert_eq!(tokens, [("\"abc", Token::String)]);
+        assert_eq!(next, State::Idle);
+    }
+
+    #[test]
+    fn module_calls() {
+        let (tokens, _) = highlighted("txt:text_outer, lists:'append'", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("txt:", Token::Module),
+                (",", Token::Operator),
+                ("lists:", Token::Module),
+                ("'append'", Token::QuotedAtom),
+            ]
+        );
+
+        let (tokens, _) = highlighted("head:-a::b, m:X", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                (":-", Token::Operator),
+                ("::", Token::Operator),
+                (",", Token::Operator),
+                (":", Token::Operator),
+                ("X", Token::Variable),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        let (tokens, _) = highlighted("42. 1.5e3 0x2A 2.5E-1", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("42", Token::Number),
+                (".", Token::Dot),
+                ("1.5e3", Token::Number),
+                ("0x2A", Token::Number),
+                ("2.5E-1", Token::Number),
+            ]
+        );
+        // No digits after the exponent
+        assert_eq!(number("1.0e", 0), 3);
+        assert_eq!(number("1e3", 0), 1);
+    }
+
+    #[test]
+    fn variables_and_operators() {
+        let (tokens, _) = highlighted("a(_X, Y) --> (Y -> !).", State::Idle);
+        assert_eq!(
+            tokens,
+            [
+                ("_X", Token::Variable),
+                (",", Token::Operator),
+                ("Y", Token::Variable),
+                ("-->", Token::HighlightOperator),
+                ("Y", Token::Variable),
+                ("->", Token::RiskyOperator),
+                ("!", Token::Operator),
+                (".", Token::Dot),
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_follow_changed_lines() {
+        let mut highlighter = PikchrHighlighter::new(&None);
+        let _ = highlighter.highlight_line("define shape { box }");
+        let used: Vec<_> = highlighter.highlight_line("shape").collect();
+        assert_eq!(used, [(0..5, PikchrToken::Macro)]);
+
+        // The definition is gone once its line is edited
+        highlighter.change_line(0);
+        let _ = highlighter.highlight_line("box");
+        let used: Vec<_> = highlighter.highlight_line("shape").collect();
+        assert_eq!(used, []);
     }
 }
@@ -0,0 +1,648 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+//! Modal editing, turned on with `modal = true` in the keybindings file.
+//!
+//! Only keys without Ctrl, Alt or Cmd are taken over, chords keep going
+//! through the keymap in every mode.
+
+use std::{fmt::Display, sync::Arc};
+
+use iced::{
+    Element, Task,
+    keyboard::{Key, key::Named},
+    widget::{
+        operation,
+        text_editor::{Action, Content, Cursor, Edit, KeyPress, Motion, Position},
+        text_input,
+    },
+};
+
+use crate::{
+    completion,
+    document::Document,
+    editor_actions_handler,
+    editor_state::Editor,
+    messages::{EditorAction, Message, VimAction, VimKey},
+};
+
+pub const COMMAND_ID: &str = "vim_command";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Normal => write!(f, "NORMAL"),
+            Mode::Insert => write!(f, "INSERT"),
+            Mode::Visual => write!(f, "VISUAL"),
+        }
+    }
+}
+
+/// Yanked or deleted text.
+#[derive(Debug, Clone)]
+struct Register {
+    text:     String,
+    /// Whole lines, pasted below or above the cursor line
+    linewise: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Vim {
+    pub mode:         Mode,
+    /// `:` command being typed, `None` when the line is closed
+    pub command_line: Option<String>,
+    count:            Option<usize>,
+    operator:         Option<char>,
+    /// `g` of `gg` typed
+    g_prefix:         bool,
+    /// Keys of the command typed so far
+    keys:             Vec<VimKey>,
+    /// Keys of the change in progress, insert mode included
+    recording:        Option<Vec<VimKey>>,
+    /// Replayed by `.`
+    last_change:      Vec<VimKey>,
+    /// `.` is replaying `last_change`, which stays as it is
+    replaying:        bool,
+    register:         Option<Register>,
+}
+
+impl Vim {
+    /// Count, operator and prefix typed so far, for the mode indicator.
+    pub fn pending(&self) -> String {
+        let count = self.count.map(|count| count.to_string()).unwrap_or_default();
+        let operator = self.operator.map(String::from).unwrap_or_default();
+        let g = if self.g_prefix { "g" } else { "" };
+        format!("{}{}{}", count, operator, g)
+    }
+
+    fn reset(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.g_prefix = false;
+        self.keys.clear();
+    }
+
+    /// Command is a change, `.` replays its keys once it's finished.
+    fn record(&mut self) {
+        if !self.replaying {
+            self.recording = Some(std::mem::take(&mut self.keys));
+        }
+    }
+
+    fn finish_change(&mut self) {
+        if self.replaying {
+            return;
+        }
+        if let Some(keys) = self.recording.take() {
+            self.last_change = keys;
+        }
+    }
+}
+
+/// Key the modal editor handles instead of the text editor, if any.
+pub fn intercept(keypress: &KeyPress) -> Option<VimKey> {
+    let modifiers = keypress.modifiers;
+    if modifiers.control() || modifiers.alt() || modifiers.logo() {
+        return None;
+    }
+    match keypress.key.as_ref() {
+        Key::Named(Named::Escape) => Some(VimKey::Escape),
+        Key::Named(Named::Enter) => Some(VimKey::Enter),
+        Key::Named(Named::Backspace) => Some(VimKey::Backspace),
+        // Arrows and the like keep working in every mode
+        Key::Named(Named::Space) | Key::Character(_) => {
+            let c = keypress.text.as_ref()?.chars().next()?;
+            Some(VimKey::Char(c))
+        },
+        _ => None,
+    }
+}
+
+pub fn handle(editor: &mut Editor, action: VimAction) -> Task<Message> {
+    match action {
+        VimAction::Key(key) => self::key(editor, key),
+        VimAction::CommandInput(command) => {
+            editor.vim.command_line = Some(command);
+            Task::none()
+        },
+        VimAction::CommandSubmitted => {
+            let command = editor.vim.command_line.take().unwrap_or_default();
+            Task::batch([operation::focus("editor"), run_command(editor, command.trim())])
+        },
+    }
+}
+
+fn key(editor: &mut Editor, key: VimKey) -> Task<Message> {
+    match editor.vim.mode {
+        Mode::Insert => insert_key(editor, key),
+        Mode::Normal | Mode::Visual => normal_key(editor, key),
+    }
+}
+
+fn insert_key(editor: &mut Editor, key: VimKey) -> Task<Message> {
+    if let Some(recording) = &mut editor.vim.recording {
+        recording.push(key);
+    }
+    match key {
+        VimKey::Escape => {
+            editor.vim.mode = Mode::Normal;
+            editor.vim.finish_change();
+            editor.completion.close();
+            let content = &mut editor.doc_mut().content;
+            if content.cursor().position.column > 0 {
+                content.perform(Action::Move(Motion::Left));
+            }
+            Task::none()
+        },
+        VimKey::Char(c) => typed(editor, Action::Edit(Edit::Insert(c))),
+        VimKey::Backspace => typed(editor, Action::Edit(Edit::Backspace)),
+        VimKey::Enter => newline(editor),
+    }
+}
+
+/// Insert mode edit, completion follows it as it does outside of modal
+/// editing.
+fn typed(editor: &mut Editor, action: Action) -> Task<Message> {
+    let task = edit(editor.doc_mut(), [action.clone()]);
+    Task::batch([task, completion::edited(editor, &action)])
+}
+
+/// Indented like Enter outside of modal editing.
+fn newline(editor: &mut Editor) -> Task<Message> {
+    let document = editor.doc_mut();
+    document.undo_stack.push(&document.content);
+    document.dirty = true;
+    let task = editor_actions_handler::handle(editor, EditorAction::NewlineIndent);
+    Task::batch([task, Task::done(Message::RunLogic), editor.scroll_to_cursor()])
+}
+
+fn motion(g_prefix: bool, c: char) -> Option<Motion> {
+    let motion = match (g_prefix, c) {
+        (false, 'h') => Motion::Left,
+        (false, 'j') => Motion::Down,
+        (false, 'k') => Motion::Up,
+        (false, 'l') | (false, ' ') => Motion::Right,
+        (false, 'w') => Motion::WordRight,
+        (false, 'b') => Motion::WordLeft,
+        (false, '0') => Motion::Home,
+        (false, '$') => Motion::End,
+        (false, 'G') => Motion::DocumentEnd,
+        (true, 'g') => Motion::DocumentStart,
+        _ => return None,
+    };
+    Some(motion)
+}
+
+fn normal_key(editor: &mut Editor, key: VimKey) -> Task<Message> {
+    let c = match key {
+        VimKey::Escape => {
+            editor.vim.reset();
+            if editor.vim.mode == Mode::Visual {
+                editor.vim.mode = Mode::Normal;
+                deselect(&mut editor.doc_mut().content);
+            }
+            return Task::none();
+        },
+        VimKey::Char(c) => c,
+        VimKey::Enter => 'j',
+        VimKey::Backspace => 'h',
+    };
+    let vim = &mut editor.vim;
+    vim.keys.push(key);
+
+    if let Some(digit) = c.to_digit(10)
+        && (digit != 0 || vim.count.is_some())
+    {
+        vim.count = Some(vim.count.unwrap_or(0) * 10 + digit as usize);
+        return Task::none();
+    }
+    if c == 'g' && !vim.g_prefix {
+        vim.g_prefix = true;
+        return Task::none();
+    }
+    let explicit_count = vim.count;
+    let count = explicit_count.unwrap_or(1);
+    let g_prefix = vim.g_prefix;
+
+    if let Some(operator) = vim.operator {
+        let task = if c == operator {
+            lines_operator(editor, operator, count)
+        } else if let Some(motion) = motion(g_prefix, c) {
+            motion_operator(editor, operator, motion, count)
+        } else {
+            Task::none()
+        };
+        editor.vim.reset();
+        return task;
+    }
+
+    if let Some(motion) = motion(g_prefix, c) {
+        editor.vim.reset();
+        // `5G` and `5gg` go to line 5
+        if let (Some(line), Motion::DocumentStart | Motion::DocumentEnd) = (explicit_count, motion) {
+            return go_to_line(editor, line);
+        }
+        let action = if editor.vim.mode == Mode::Visual {
+            Action::Select(motion)
+        } else {
+            Action::Move(motion)
+        };
+        let content = &mut editor.doc_mut().content;
+        for _ in 0..count {
+            content.perform(action.clone());
+        }
+        return editor.scroll_to_cursor();
+    }
+
+    if editor.vim.mode == Mode::Visual {
+        return visual_command(editor, c);
+    }
+
+    match c {
+        'd' | 'y' => {
+            editor.vim.operator = Some(c);
+            return Task::none();
+        },
+        _ => (),
+    }
+    let task = normal_command(editor, c, count);
+    editor.vim.reset();
+    task
+}
+
+fn normal_command(editor: &mut Editor, c: char, count: usize) -> Task<Message> {
+    match c {
+        'i' | 'a' | 'I' | 'A' | 'o' | 'O' => {
+            editor.vim.record();
+            editor.vim.mode = Mode::Insert;
+            let content = &mut editor.doc_mut().content;
+            match c {
+                'a' if !at_line_end(content) => content.perform(Action::Move(Motion::Right)),
+                'I' => content.perform(Action::Move(Motion::Home)),
+                'A' => content.perform(Action::Move(Motion::End)),
+                'o' => {
+                    content.perform(Action::Move(Motion::End));
+                    return newline(editor);
+                },
+                'O' => {
+                    return edit(
+                        editor.doc_mut(),
+                        [
+                            Action::Move(Motion::Home),
+                            Action::Edit(Edit::Enter),
+                            Action::Move(Motion::Up),
+                        ],
+                    );
+                },
+                _ => (),
+            }
+            Task::none()
+        },
+        'x' => {
+            editor.vim.record();
+            editor.vim.finish_change();
+            let content = &mut editor.doc_mut().content;
+            for _ in 0..count {
+                if at_line_end(content) {
+                    break;
+                }
+                content.perform(Action::Select(Motion::Right));
+            }
+            delete_selection(editor)
+        },
+        'D' => {
+            editor.vim.record();
+            editor.vim.finish_change();
+            motion_operator(editor, 'd', Motion::End, 1)
+        },
+        'p' | 'P' => {
+            editor.vim.record();
+            editor.vim.finish_change();
+            let Some(register) = editor.vim.register.clone() else {
+                return Task::none();
+            };
+            let text = register.text.repeat(count);
+            if register.linewise {
+                paste_lines(editor.doc_mut(), &text, c == 'p')
+            } else {
+                let content = &editor.doc().content;
+                let mut actions = vec![];
+                if c == 'p' && !at_line_end(content) {
+                    actions.push(Action::Move(Motion::Right));
+                }
+                actions.push(Action::Edit(Edit::Paste(Arc::new(text))));
+                edit(editor.doc_mut(), actions)
+            }
+        },
+        'v' => {
+            editor.vim.mode = Mode::Visual;
+            Task::none()
+        },
+        'u' => Task::done(Message::Undo),
+        '.' => {
+            let keys = editor.vim.last_change.clone();
+            editor.vim.keys.clear();
+            editor.vim.replaying = true;
+            let tasks = (0..count)
+                .flat_map(|_| keys.clone())
+                .map(|key| self::key(editor, key))
+                .collect::<Vec<_>>();
+            editor.vim.replaying = false;
+            Task::batch(tasks)
+        },
+        ':' => {
+            editor.vim.command_line = Some(String::new());
+            operation::focus(COMMAND_ID)
+        },
+        _ => Task::none(),
+    }
+}
+
+fn visual_command(editor: &mut Editor, c: char) -> Task<Message> {
+    editor.vim.reset();
+    match c {
+        'y' => {
+            yank_selection(editor);
+            editor.vim.mode = Mode::Normal;
+            deselect(&mut editor.doc_mut().content);
+            Task::none()
+        },
+        'd' | 'x' => {
+            editor.vim.mode = Mode::Normal;
+            delete_selection(editor)
+        },
+        'v' => {
+            editor.vim.mode = Mode::Normal;
+            deselect(&mut editor.doc_mut().content);
+            Task::none()
+        },
+        _ => Task::none(),
+    }
+}
+
+/// `dd` and `yy`
+fn lines_operator(editor: &mut Editor, operator: char, count: usize) -> Task<Message> {
+    let document = editor.doc_mut();
+    let line = document.content.cursor().position.line;
+    let text = document.content.text();
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let end = (line + count).min(lines.len());
+    let mut yanked = lines[line..end].concat();
+    if !yanked.ends_with('\n') {
+        yanked.push('\n');
+    }
+    editor.vim.register = Some(Register {
+        text:     yanked,
+        linewise: true,
+    });
+    if operator == 'y' {
+        return Task::none();
+    }
+
+    editor.vim.record();
+    editor.vim.finish_change();
+    let mut rest = lines[..line].concat() + &lines[end..].concat();
+    // Deleting the last lines leaves the newline above them behind
+    if end == lines.len() && !text.ends_with('\n') {
+        rest.pop();
+    }
+    let document = editor.doc_mut();
+    replace_text(document, &rest, line);
+    Task::batch([Task::done(Message::RunLogic), editor.scroll_to_cursor()])
+}
+
+/// `d` or `y` followed by a motion
+fn motion_operator(editor: &mut Editor, operator: char, motion: Motion, count: usize) -> Task<Message> {
+    let content = &mut editor.doc_mut().content;
+    let start = content.cursor();
+    for _ in 0..count {
+        content.perform(Action::Select(motion));
+    }
+    if operator == 'd' {
+        editor.vim.record();
+        editor.vim.finish_change();
+        delete_selection(editor)
+    } else {
+        yank_selection(editor);
+        editor.doc_mut().content.move_to(start);
+        Task::none()
+    }
+}
+
+fn yank_selection(editor: &mut Editor) {
+    if let Some(text) = editor.doc().content.selection() {
+        editor.vim.register = Some(Register {
+            text,
+            linewise: false,
+        });
+    }
+}
+
+fn delete_selection(editor: &mut Editor) -> Task<Message> {
+    let Some(text) = editor.doc().content.selection() else {
+        return Task::none();
+    };
+    editor.vim.register = Some(Register {
+        text,
+        linewise: false,
+    });
+    edit(editor.doc_mut(), [Action::Edit(Edit::Delete)])
+}
+
+fn paste_lines(document: &mut Document, text: &str, below: bool) -> Task<Message> {
+    let line = document.content.cursor().position.line;
+    let at = if below { line + 1 } else { line };
+    let current = document.content.text();
+    let mut lines: Vec<String> = current.split_inclusive('\n').map(String::from).collect();
+    let mut text = text.to_string();
+    if at >= lines.len() {
+        // Below the last line, which has no newline to end it
+        if let Some(last) = lines.last_mut().filter(|last| !last.ends_with('\n')) {
+            last.push('\n');
+            text.pop();
+        }
+    }
+    lines.insert(at.min(lines.len()), text);
+    replace_text(document, &lines.concat(), at);
+    Task::done(Message::RunLogic)
+}
+
+/// Line operations rebuild the content, like "Replace all" does.
+fn replace_text(document: &mut Document, text: &str, line: usize) {
+    document.undo_stack.push(&document.content);
+    document.dirty = true;
+    document.content = Content::with_text(text);
+    let line = line.min(document.content.line_count().saturating_sub(1));
+    document.content.move_to(Cursor {
+        position:  Position { line, column: 0 },
+        selection: None,
+    });
+}
+
+/// Performs `actions` as one undo step.
+fn edit(document: &mut Document, actions: impl IntoIterator<Item = Action>) -> Task<Message> {
+    document.undo_stack.push(&document.content);
+    document.dirty = true;
+    for action in actions {
+        document.content.perform(action);
+    }
+    Task::done(Message::RunLogic)
+}
+
+fn at_line_end(content: &Content) -> bool {
+    let position = content.cursor().position;
+    content
+        .line(position.line)
+        .is_none_or(|line| position.column >= line.text.len())
+}
+
+fn deselect(content: &mut Content) {
+    let position = content.cursor().position;
+    content.move_to(Cursor {
+        position,
+        selection: None,
+    });
+}
+
+/// 1-based, as typed.
+fn go_to_line(editor: &mut Editor, line: usize) -> Task<Message> {
+    editor.jump_to_line(line.saturating_sub(1))
+}
+
+fn run_command(editor: &mut Editor, command: &str) -> Task<Message> {
+    let document = editor.doc();
+    let id = document.id;
+    // A blank tab has nothing to lose
+    let unsaved = document.dirty && !document.is_blank();
+    match command {
+        "" => Task::none(),
+        "w" => Task::done(Message::SaveRequested),
+        "q" if unsaved => {
+            let message = String::from("E37: No write since last change (add ! to override)");
+            editor.doc_mut().last_error.set(message);
+            Task::none()
+        },
+        "q" | "q!" => Task::done(Message::DiscardAndCloseRequested(id)),
+        // Only writes when there are changes
+        "x" if !unsaved => Task::done(Message::DiscardAndCloseRequested(id)),
+        "wq" | "x" => Task::done(Message::SaveAndCloseRequested(id)),
+        _ => match command.parse::<usize>() {
+            Ok(line) => go_to_line(editor, line),
+            Err(_) => {
+                let message = format!("Not an editor command: {}", command);
+                editor.doc_mut().last_error.set(message);
+                Task::none()
+            },
+        },
+    }
+}
+
+/// `:` line above the editor.
+pub fn view(command: &str) -> Element<'_, Message> {
+    text_input(":", command)
+        .id(COMMAND_ID)
+        .on_input(|command| Message::Vim(VimAction::CommandInput(command)))
+        .on_submit(Message::Vim(VimAction::CommandSubmitted))
+        .size(12)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperatingMode;
+
+    fn editor(dirty: bool) -> Editor {
+        with_text("a.", dirty)
+    }
+
+    fn with_text(text: &str, dirty: bool) -> Editor {
+        let mut document = Document::new(0, text, OperatingMode::PrologMode);
+        document.current_file = Some("a.pl".into());
+        document.dirty = dirty;
+        let mut editor = Editor::default();
+        editor.documents = vec![document];
+        // Pausing for completion needs a runtime
+        editor.keymap.completion_delay = 0;
+        editor
+    }
+
+    fn last_error(editor: &mut Editor) -> String {
+        let document = editor.doc_mut();
+        document.last_error.commit();
+        document.last_error.get()
+    }
+
+    #[test]
+    fn quit_refuses_unsaved_changes() {
+        let mut editor = editor(true);
+        let _ = run_command(&mut editor, "q");
+        assert!(last_error(&mut editor).starts_with("E37:"));
+    }
+
+    #[test]
+    fn quit_takes_saved_or_forced() {
+        let mut saved = editor(false);
+        let _ = run_command(&mut saved, "q");
+        assert_eq!(last_error(&mut saved), "");
+
+        let mut forced = editor(true);
+        let _ = run_command(&mut forced, "q!");
+        assert_eq!(last_error(&mut forced), "");
+    }
+
+    #[test]
+    fn unknown_command_is_reported() {
+        let mut editor = editor(false);
+        let _ = run_command(&mut editor, "frobnicate");
+        assert_eq!(last_error(&mut editor), "Not an editor command: frobnicate");
+    }
+
+    fn press(editor: &mut Editor, keys: &str) {
+        for c in keys.chars() {
+            let _ = key(editor, VimKey::Char(c));
+        }
+    }
+
+    #[test]
+    fn repeat_replays_the_last_change() {
+        let mut editor = with_text("a\nb\nc\nd\n", false);
+        press(&mut editor, "dd..");
+        assert_eq!(editor.doc().content.text(), "d\n");
+        assert_eq!(
+            editor.vim.last_change,
+            [VimKey::Char('d'), VimKey::Char('d')]
+        );
+    }
+
+    #[test]
+    fn repeat_replays_inserts() {
+        let mut editor = with_text("\n", false);
+        press(&mut editor, "ix");
+        let _ = key(&mut editor, VimKey::Escape);
+        press(&mut editor, "..");
+        assert_eq!(editor.doc().content.text(), "xxx\n");
+        assert_eq!(
+            editor.vim.last_change,
+            [VimKey::Char('i'), VimKey::Char('x'), VimKey::Escape]
+        );
+    }
+}
@@ -18,9 +18,10 @@ fn main() {
 
 fn build_pikchr() {
     cc::Build::new()
-        .file("native/pikchr/pikchr.c")
+        .file("native/pikchr_ext.c")
         .compile("pikchr");
 
     println!("cargo:rerun-if-changed=native/pikchr/pikchr.c");
+    println!("cargo:rerun-if-changed=native/pikchr_ext.c");
 }
 
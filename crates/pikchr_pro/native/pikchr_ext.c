@@ -0,0 +1,77 @@
+/*
+** This file is part of pikchr.pl.
+**
+** pikchr.pl is free software: you can redistribute it and/or modify it under
+** the terms of the GNU General Public License as published by the Free
+** Software Foundation, version 3 of the License.
+**
+** pikchr.pl is distributed in the hope that it will be useful, but WITHOUT
+** ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+** FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for
+** more details.
+**
+** You should have received a copy of the GNU General Public License along
+** with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+** Extensions to upstream Pikchr, applied without touching pikchr.c so it
+** can be replaced with a fresh copy.
+**
+** Include PIKCHR_OBJECT_IDS among the mFlag bits to wrap every rendered
+** object in <g id="pikchr-LINE-N">, where LINE is the 1-based source line
+** of the object (0 if unknown) and N makes the id unique.
+*/
+#define PIKCHR_OBJECT_IDS 0x0100
+
+struct Pik;
+struct PObj;
+static void pik_render_with_id(
+  struct Pik *p,
+  struct PObj *pObj,
+  void (*xRender)(struct Pik*, struct PObj*)
+);
+
+/* The only call site of xRender() is in pik_elist_render() */
+#define xRender(P,O) pik_render_with_id(P, O, xRender)
+#define pikchr pikchr_upstream
+#include "pikchr/pikchr.c"
+#undef pikchr
+#undef xRender
+
+static _Thread_local unsigned int nObjId;
+
+static void pik_render_with_id(
+  Pik *p,
+  PObj *pObj,
+  void (*xRender)(Pik*, PObj*)
+){
+  const char *z = pObj->errTok.z;
+  int iLine = 0;
+  char buf[100];
+  if( (p->mFlags & PIKCHR_OBJECT_IDS)==0 ){
+    xRender(p, pObj);
+    return;
+  }
+  if( z>=p->sIn.z && z<p->sIn.z+p->sIn.n ){
+    iLine = 1;
+    while( z>p->sIn.z ){
+      if( *(--z)=='\n' ) iLine++;
+    }
+  }
+  snprintf(buf, sizeof(buf)-1, "<g id=\"pikchr-%d-%u\">\n", iLine, nObjId++);
+  pik_append(p, buf, -1);
+  xRender(p, pObj);
+  pik_append(p, "</g>\n", -1);
+}
+
+char *pikchr(
+  const char *zText,
+  const char *zClass,
+  unsigned int mFlags,
+  int *pnWidth,
+  int *pnHeight
+){
+  nObjId = 0;
+  return pikchr_upstream(zText, zClass, mFlags, pnWidth, pnHeight);
+}
@@ -11,14 +11,15 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use anyhow::Result;
-
+#[cfg(feature = "sync")]
 use crate::prolog::{RenderError, engine};
 
 pub mod pikchr;
 pub mod prolog;
+pub mod renderer;
 pub mod types;
 
+#[cfg(feature = "sync")]
 pub fn prolog_to_svg_string(input: String) -> Result<String, RenderError> {
     engine::trealla::Engine::init();
     let result = engine::trealla::Engine::process_diagram(vec![input])?;
@@ -11,20 +11,111 @@
 // You should have received a copy of the GNU General Public License along
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
-use std::io::{self, Read};
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-pub mod pikchr;
-pub mod prolog;
-use pikchr_pro::prolog_to_svg_string;
-mod types;
+use pikchr_pro::{
+    prolog::RenderError,
+    prolog_to_svg_string,
+    renderer::{Options, Renderer},
+};
+
+const USAGE: &str = "Usage: pikchr_pro [--tpl <path/to/tpl.wasm>] < diagram.pl > diagram.svg";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+enum Invocation {
+    Run { tpl: Option<PathBuf> },
+    Help,
+}
+
+fn main() -> io::Result<ExitCode> {
+    let tpl_path = match parse_args(std::env::args().skip(1)) {
+        Ok(Invocation::Run { tpl }) => tpl,
+        Ok(Invocation::Help) => {
+            println!("{}", USAGE);
+            return Ok(ExitCode::SUCCESS);
+        },
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return Ok(ExitCode::from(2));
+        },
+    };
 
-fn main() -> io::Result<()> {
     let mut buffer = String::new();
     io::stdin().read_to_string(&mut buffer)?;
 
-    match prolog_to_svg_string(buffer) {
+    match render(buffer, tpl_path) {
         Err(e) => eprintln!("Error: {}", e),
         Ok(result) => println!("{}", result),
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+fn render(input: String, tpl_path: Option<PathBuf>) -> Result<String, RenderError> {
+    match tpl_path {
+        None => prolog_to_svg_string(input),
+        Some(tpl_path) => {
+            let options = Options {
+                tpl_path: Some(tpl_path),
+                ..Default::default()
+            };
+            let svg = Renderer::new(options)?.render(&input)?;
+            Ok(svg.into_inner())
+        },
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Invocation, String> {
+    let mut tpl = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tpl" => {
+                let path = args.next().ok_or("--tpl requires a path")?;
+                tpl = Some(PathBuf::from(path));
+            },
+            "-h" | "--help" => return Ok(Invocation::Help),
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    Ok(Invocation::Run { tpl })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Invocation, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_run_bundled_build() {
+        assert_eq!(parse(&[]), Ok(Invocation::Run { tpl: None }));
+    }
+
+    #[test]
+    fn tpl_takes_a_path() {
+        assert_eq!(
+            parse(&["--tpl", "build/tpl.wasm"]),
+            Ok(Invocation::Run {
+                tpl: Some(PathBuf::from("build/tpl.wasm")),
+            })
+        );
+        assert!(parse(&["--tpl"]).is_err());
+    }
+
+    #[test]
+    fn help_wins_over_other_arguments() {
+        assert_eq!(parse(&["--tpl", "tpl.wasm", "-h"]), Ok(Invocation::Help));
+        assert_eq!(parse(&["--help"]), Ok(Invocation::Help));
+    }
+
+    #[test]
+    fn unknown_argument_is_rejected() {
+        assert_eq!(parse(&["--fast"]), Err(String::from("Unknown argument: --fast")));
+    }
 }
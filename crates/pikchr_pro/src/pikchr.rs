@@ -32,6 +32,46 @@ unsafe extern "C" {
     fn free(p: *mut c_void);
 }
 
+/// Error messages come out as plain text instead of HTML.
+pub const PLAINTEXT_ERRORS: i32 = 0x0001;
+/// Inverts colors.
+pub const DARK_MODE: i32 = 0x0002;
+/// Wraps every object in `<g id="pikchr-LINE-N">`, see [`object_line`].
+/// Not part of upstream Pikchr (`native/pikchr_ext.c`).
+pub const OBJECT_IDS: i32 = 0x0100;
+
+/// 0-based source line of the error in Pikchr error message.
+///
+/// Pikchr prints context lines as `/*   N */  code` followed by `^^^`
+/// under the offending token.
+pub fn error_line(message: &str) -> Option<usize> {
+    let mut context_line = None;
+    for line in message.lines() {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && trimmed.chars().all(|c| c == '^') {
+            return context_line;
+        }
+        context_line = line
+            .trim_start()
+            .strip_prefix("/*")
+            .and_then(|rest| rest.split_once("*/"))
+            .and_then(|(number, _)| number.trim().parse::<usize>().ok())
+            .and_then(|number| number.checked_sub(1));
+    }
+    None
+}
+
+/// 0-based source line of an object, taken from its [`OBJECT_IDS`] group id.
+pub fn object_line(id: &str) -> Option<usize> {
+    let line: usize = id
+        .strip_prefix("pikchr-")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()?;
+    line.checked_sub(1)
+}
+
 #[derive(Debug)]
 pub struct PikchrResult {
     ptr:        *mut c_char,
@@ -89,6 +129,9 @@ impl PikchrCode {
         let s = String::from(input.as_ref());
         Self(s)
     }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
     pub fn into_inner(self) -> String {
         self.0
     }
@@ -132,3 +175,29 @@ pub fn render(text: &str, class_name: Option<&str>, flags: i32) -> Result<Pikchr
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_ids_point_at_source_lines() {
+        let svg = render("box\n\ncircle \"A\"\n", None, OBJECT_IDS).unwrap();
+        let lines: Vec<_> = svg
+            .as_str()
+            .split("<g id=\"")
+            .skip(1)
+            .filter_map(|rest| object_line(&rest[..rest.find('"')?]))
+            .collect();
+
+        assert_eq!(lines, vec![0, 2]);
+    }
+
+    #[test]
+    fn error_line_points_at_marked_line() {
+        let result = render("box\narrow\nbox wid foo\n", None, PLAINTEXT_ERRORS).unwrap();
+
+        assert!(result.is_error());
+        assert_eq!(error_line(result.as_str()), Some(2));
+    }
+}
@@ -17,7 +17,7 @@ pub mod engine;
 
 pub(crate) static DIAGRAM_INIT: &str = include_str!("../native/prolog/init.pl");
 
-type Queries = Vec<String>;
+pub(crate) type Queries = Vec<String>;
 
 #[derive(Debug, Error, Clone)]
 pub enum RenderError {
@@ -27,13 +27,73 @@ pub enum RenderError {
     PikchrError(String),
     #[error("Anyhow: {0}")]
     AnyhowError(String),
+    #[error("Render cancelled")]
+    Cancelled,
     #[error("Fmt error: {0}")]
     FormatError(#[from] std::fmt::Error),
 }
 
+/// 0-based line of the diagram source mentioned in Trealla error message.
+///
+/// Trealla reports lines of the whole consulted program (`user:N`), diagram
+/// source comes right after the runner (see `engine::trealla::diagram_input`).
+pub fn error_line(message: &str) -> Option<usize> {
+    // Syntax errors name the consulted stdin `./`
+    let line = ["user:", "./:", "line "].iter().find_map(|marker| {
+        message.match_indices(marker).find_map(|(index, _)| {
+            let digits: String = message[index + marker.len()..]
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            digits.parse::<usize>().ok()
+        })
+    })?;
+    let runner_lines = DIAGRAM_INIT.matches('\n').count() + 1;
+    line.checked_sub(runner_lines + 1)
+}
+
 impl From<anyhow::Error> for RenderError {
     fn from(value: anyhow::Error) -> Self {
         RenderError::AnyhowError(value.to_string())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line Trealla reports for 0-based `line` of the diagram source.
+    fn program_line(line: usize) -> usize {
+        DIAGRAM_INIT.matches('\n').count() + 2 + line
+    }
+
+    #[test]
+    fn user_line_maps_to_diagram_source() {
+        let message = format!("error(syntax_error(operator_expected),user:{}).", program_line(2));
+        assert_eq!(error_line(&message), Some(2));
+    }
+
+    #[test]
+    fn line_marker_maps_to_diagram_source() {
+        let message = format!("Warning: singleton variables [X], line {}", program_line(0));
+        assert_eq!(error_line(&message), Some(0));
+    }
+
+    #[test]
+    fn runner_lines_are_not_diagram_source() {
+        assert_eq!(error_line("error(existence_error(procedure,foo/0),user:1)."), None);
+    }
+
+    #[test]
+    fn message_without_line() {
+        assert_eq!(error_line("error(existence_error(procedure,diagram//0),run/0)."), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn trealla_error_points_at_diagram_source() {
+        let input = String::from("diagram --> \"box;\".\nbroken --> ).\n");
+        let error = engine::trealla::Engine::process_diagram(vec![input]).unwrap_err();
+        assert_eq!(error_line(&error.to_string()), Some(1), "{}", error);
+    }
+}
@@ -12,8 +12,10 @@
 // with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
 
 
+pub use trealla_wasm::CancellationToken;
+
 use crate::{
-    prolog::{DIAGRAM_INIT,  Queries, RenderError},
+    prolog::{DIAGRAM_INIT, Queries, RenderError},
     types::PikchrCode,
 };
 
@@ -26,24 +28,47 @@ macro_rules! process_diagram_impl {
         await_: $($await_token:tt)*
     ) => {
             pub $($async_kw)? fn process_diagram(input: Queries) -> Result<PikchrCode, RenderError> {
-                let mut diagram_input = input.clone();
-                diagram_input.insert(0, String::from(DIAGRAM_INIT));
-                let diagram_input = diagram_input.iter().cloned().collect::<Vec<_>>().join("\n");
+                Self::process_diagram_with_token(input, &CancellationToken::new())
+                $($await_token)*
+            }
 
+            /// Same as `process_diagram`, but the underlying Prolog run stops
+            /// as soon as `token` is cancelled, returning [`RenderError::Cancelled`].
+            pub $($async_kw)? fn process_diagram_with_token(
+                input: Queries,
+                token: &CancellationToken,
+            ) -> Result<PikchrCode, RenderError> {
+                let diagram_input = diagram_input(input);
 
-                trealla_wasm::$func::run_prolog("run", &diagram_input)
+                trealla_wasm::$func::run_prolog_with_token("run", &diagram_input, token)
                 $($await_token)*
-                .map_err(|e| RenderError::PrologError(format!("{}", e)))
+                .map_err(to_render_error)
                 .map(PikchrCode::new)
             }
     };
 }
 
+/// Joins queries into a single Prolog program, prepended with diagram runner.
+pub(crate) fn diagram_input(input: Queries) -> String {
+    let mut diagram_input = input;
+    diagram_input.insert(0, String::from(DIAGRAM_INIT));
+    diagram_input.join("\n")
+}
+
+pub(crate) fn to_render_error(e: anyhow::Error) -> RenderError {
+    if e.is::<trealla_wasm::Cancelled>() {
+        RenderError::Cancelled
+    } else {
+        RenderError::PrologError(format!("{}", e))
+    }
+}
+
 #[cfg(feature = "sync")]
 pub struct Engine{}
 #[cfg(feature = "async")]
 pub struct EngineAsync{}
 
+#[cfg(feature = "sync")]
 impl Engine {
     pub fn init() {
         trealla_wasm::Engine::init();
@@ -55,6 +80,7 @@ impl Engine {
         await_:
     );
 }
+#[cfg(feature = "async")]
 impl EngineAsync {
     pub fn init() {
         trealla_wasm::EngineAsync::init();
@@ -75,11 +101,12 @@ mod tests {
         ($name: ident, $inp:literal, $out:literal) => {
             mod $name {
                 use super::*;
-                prolog_test!(@common test,,[ ],Engine,sync_version,$inp,$out);
-                prolog_test!(@common tokio::test,async,[.await],EngineAsync,async_version, $inp, $out);
+                prolog_test!(@common "sync",test,,[ ],Engine,sync_version,$inp,$out);
+                prolog_test!(@common "async",tokio::test,async,[.await],EngineAsync,async_version, $inp, $out);
             }
         };
-        (@common $test_type:meta,$($async_kw:ident)?,[$($await_token:tt)*],$module:ident,$name:ident, $inp: literal, $out: literal) => {
+        (@common $feature:literal,$test_type:meta,$($async_kw:ident)?,[$($await_token:tt)*],$module:ident,$name:ident, $inp: literal, $out: literal) => {
+            #[cfg(feature = $feature)]
             #[$test_type]
             $($async_kw)? fn $name() {
                 let input = $inp;
@@ -140,4 +167,18 @@ diagram --> text("Test", small).
         r#"diagram --> "box;", "arrow;", "box"."#,
         "box;arrow;box"
     );
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancelled_render() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            canceller.cancel();
+        });
+        let input = String::from("spin --> spin.\ndiagram --> spin.");
+        let got = EngineAsync::process_diagram_with_token(vec![input], &token).await;
+        assert!(matches!(got, Err(RenderError::Cancelled)));
+    }
 }
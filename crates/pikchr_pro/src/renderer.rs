@@ -0,0 +1,162 @@
+// This file is part of pikchr.pl.
+//
+// pikchr.pl is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software
+// Foundation, version 3 of the License.
+//
+// pikchr.pl is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+// A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with pikchr.pl. If not, see <https://www.gnu.org/licenses/>.
+
+pub use trealla_wasm::{CancellationToken, Options};
+
+use crate::{
+    pikchr,
+    prolog::{
+        Queries, RenderError,
+        engine::trealla::{diagram_input, to_render_error},
+    },
+    types::{PikchrCode, SvgString},
+};
+
+macro_rules! renderer_impl {
+    (
+        runtime: $runtime:ident,
+        async_: $($async_kw:ident)?,
+        await_: $($await_token:tt)*
+    ) => {
+        /// Creates renderer with its own Prolog runtime.
+        ///
+        /// It compiles Trealla module, so it's as slow as the first render
+        /// of the default engine.
+        pub fn new(options: Options) -> Result<Self, RenderError> {
+            Ok(Self {
+                runtime:      trealla_wasm::$runtime::new(options)?,
+                modules:      Vec::new(),
+                pikchr_class: None,
+                pikchr_flags: 0,
+            })
+        }
+
+        /// Prolog sources consulted before every diagram.
+        pub fn with_modules(mut self, modules: Queries) -> Self {
+            self.modules = modules;
+            self
+        }
+
+        /// Class and flags passed to Pikchr, see `pikchr.h` for flag values.
+        pub fn with_pikchr_options(mut self, class: Option<String>, flags: i32) -> Self {
+            self.pikchr_class = class;
+            self.pikchr_flags = flags;
+            self
+        }
+
+        pub fn options(&self) -> &Options {
+            self.runtime.options()
+        }
+
+        /// Runs `diagram//0` from `input` and returns generated Pikchr code.
+        pub $($async_kw)? fn render_prolog(&self, input: &str) -> Result<PikchrCode, RenderError> {
+            self.render_prolog_with_token(input, &CancellationToken::new())
+            $($await_token)*
+        }
+
+        /// Same as `render_prolog`, but stops as soon as `token` is cancelled.
+        pub $($async_kw)? fn render_prolog_with_token(
+            &self,
+            input: &str,
+            token: &CancellationToken,
+        ) -> Result<PikchrCode, RenderError> {
+            let mut queries = vec![String::from(input)];
+            queries.extend(self.modules.iter().cloned());
+            let diagram_input = diagram_input(queries);
+
+            self.runtime
+                .run_prolog_with_token("run", &diagram_input, token)
+                $($await_token)*
+                .map_err(to_render_error)
+                .map(PikchrCode::new)
+        }
+
+        /// Renders Pikchr code into SVG.
+        pub fn render_pikchr(&self, code: &PikchrCode) -> Result<SvgString, RenderError> {
+            let result = pikchr::render(
+                code.as_str(),
+                self.pikchr_class.as_deref(),
+                self.pikchr_flags,
+            )
+            .map_err(RenderError::PikchrError)?;
+            if result.is_error() {
+                return Err(RenderError::PikchrError(result.into_string()));
+            }
+            Ok(result.into())
+        }
+
+        /// Renders Prolog `input` straight into SVG.
+        pub $($async_kw)? fn render(&self, input: &str) -> Result<SvgString, RenderError> {
+            let code = self.render_prolog(input) $($await_token)* ?;
+            self.render_pikchr(&code)
+        }
+    };
+}
+
+/// Blocking renderer owning its Prolog runtime, modules and options.
+///
+/// Unlike the default engine it can be created many times, e.g. with
+/// different preopened directories or memory limits.
+#[cfg(feature = "sync")]
+pub struct Renderer {
+    runtime:      trealla_wasm::Runtime,
+    modules:      Queries,
+    pikchr_class: Option<String>,
+    pikchr_flags: i32,
+}
+
+/// Async counterpart of [`Renderer`].
+#[cfg(feature = "async")]
+pub struct RendererAsync {
+    runtime:      trealla_wasm::RuntimeAsync,
+    modules:      Queries,
+    pikchr_class: Option<String>,
+    pikchr_flags: i32,
+}
+
+#[cfg(feature = "sync")]
+impl Renderer {
+    renderer_impl!(
+        runtime: Runtime,
+        async_: ,
+        await_:
+    );
+}
+
+#[cfg(feature = "async")]
+impl RendererAsync {
+    renderer_impl!(
+        runtime: RuntimeAsync,
+        async_: async,
+        await_: .await
+    );
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renderers_keep_own_modules() {
+        let input = r#"diagram --> shape."#;
+        let boxes = Renderer::new(Options::default())
+            .unwrap()
+            .with_modules(vec![String::from(r#"shape --> "box;"."#)]);
+        let circles = Renderer::new(Options::default())
+            .unwrap()
+            .with_modules(vec![String::from(r#"shape --> "circle;"."#)]);
+
+        assert_eq!(boxes.render_prolog(input).unwrap().into_inner(), "box;");
+        assert_eq!(circles.render_prolog(input).unwrap().into_inner(), "circle;");
+    }
+}
@@ -18,6 +18,8 @@ fn build_optimized_wasm() {
 
     let mut config = Config::new();
     config.cranelift_opt_level(wasmtime::OptLevel::Speed);
+    // Must match runtime config, otherwise deserialization is rejected
+    config.epoch_interruption(true);
 
     if let Err(e) = config.target(&target_triple) {
         println!(
@@ -1,17 +1,42 @@
-use std::{fmt::Write, sync::OnceLock};
-use anyhow::{Context, Result, anyhow};
-use wasmtime::{Linker, Module, Store};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use thiserror::Error;
+use wasmtime::{Linker, Module, Store, StoreLimits, StoreLimitsBuilder, UpdateDeadline};
 use wasmtime_wasi::{
     DirPerms, FilePerms, WasiCtxBuilder,
     p1::{self, WasiP1Ctx},
     p2::pipe::{MemoryInputPipe, MemoryOutputPipe},
 };
 #[cfg(feature = "sync")]
-static RUNTIME_SYNC: OnceLock<PrologRuntime> = OnceLock::new();
+static RUNTIME_SYNC: std::sync::OnceLock<Runtime> = std::sync::OnceLock::new();
 #[cfg(feature = "async")]
-static RUNTIME_ASYNC: OnceLock<PrologRuntime> = OnceLock::new();
+static RUNTIME_ASYNC: std::sync::OnceLock<RuntimeAsync> = std::sync::OnceLock::new();
 
+/// Module compiled ahead of time by build script.
+#[cfg(precompiled_wasm)]
 static TPL_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tpl.bin"));
+/// Raw Trealla build, compiled at runtime. Only embedded when there's no AOT
+/// artifact, or with `raw-wasm` for engines the artifact doesn't fit.
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm"))]
+static TPL_WASM: &[u8] = include_bytes!("../native/tpl/tpl.wasm");
+
+/// How often engine epoch is bumped. Bounds the time a cancelled run keeps
+/// executing before it's interrupted.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Import module every supported Trealla build links against.
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Default size of captured Prolog output.
+const OUTPUT_CAPACITY: usize = 65535;
 
 type WasiCtxWithCtx = (
     wasmtime_wasi::p1::WasiP1Ctx,
@@ -19,49 +44,154 @@ type WasiCtxWithCtx = (
     wasmtime_wasi::p2::pipe::MemoryOutputPipe,
 );
 
+/// Handle for interrupting a running goal from the outside.
+///
+/// Clones share the same state, so cancelling any of them stops the run
+/// at the next epoch tick.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Error returned when run was interrupted through [`CancellationToken`].
+#[derive(Debug, Error)]
+#[error("Prolog run cancelled")]
+pub struct Cancelled;
+
+/// Settings of a single [`Runtime`] or [`RuntimeAsync`].
+///
+/// Every runtime owns its engine, so runtimes created with different
+/// options can be used side by side.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Base wasmtime configuration. Async support and epoch interruption
+    /// are always set by the runtime on top of it.
+    pub config:          wasmtime::Config,
+    /// Host directories exposed to Prolog as `(host path, guest path)`.
+    pub preopens:        Vec<(PathBuf, String)>,
+    /// Upper bound of guest memory for a single run, in bytes.
+    pub memory_limit:    Option<usize>,
+    /// Upper bound of captured output for a single run, in bytes.
+    pub output_capacity: usize,
+    /// Trealla build to use instead of the bundled one. Compiled artifact
+    /// is cached next to it with `.cwasm` extension.
+    pub tpl_path:        Option<PathBuf>,
+    /// Where bundled build compiled at runtime is kept between launches.
+    /// `None` disables the cache.
+    pub cache_dir:       Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config:          wasmtime::Config::new(),
+            preopens:        vec![(PathBuf::from("."), String::from("/"))],
+            memory_limit:    None,
+            output_capacity: OUTPUT_CAPACITY,
+            tpl_path:        None,
+            cache_dir:       default_cache_dir(),
+        }
+    }
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|dirs| dirs.cache_dir().join("trealla-wasm"))
+}
+
 pub(crate) struct LinkerState {
-    pub wasi: WasiP1Ctx,
+    pub wasi:   WasiP1Ctx,
+    pub limits: StoreLimits,
+}
+
+/// Keeps bumping engine epoch until dropped.
+struct EpochTicker(Arc<AtomicBool>);
+
+impl EpochTicker {
+    fn spawn(engine: &wasmtime::Engine) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let engine = engine.clone();
+        std::thread::spawn(move || {
+            while !thread_stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            }
+        });
+        Self(stopped)
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
-pub(crate) struct PrologRuntime {
-    pub engine: wasmtime::Engine,
-    pub module: Module,
-    pub linker: Linker<LinkerState>,
+/// Blocking Prolog runtime owning its engine, compiled module and options.
+#[cfg(feature = "sync")]
+pub struct Runtime {
+    engine:  wasmtime::Engine,
+    module:  Module,
+    linker:  Linker<LinkerState>,
+    options: Options,
+    _ticker: EpochTicker,
 }
 
-macro_rules! get_runtime_impl {
+/// Async counterpart of [`Runtime`].
+#[cfg(feature = "async")]
+pub struct RuntimeAsync {
+    engine:  wasmtime::Engine,
+    module:  Module,
+    linker:  Linker<LinkerState>,
+    options: Options,
+    _ticker: EpochTicker,
+}
+
+macro_rules! runtime_new_impl {
     (
-        runtime: $runtime:ident,
         async_support: $async_support:literal,
         linker_fn: $linker_fn:ident
+    ) => {
+        /// Creates runtime with its own engine and compiled Trealla module.
+        ///
+        /// Compilation is the slow part, so runtimes are meant to be reused.
+        pub fn new(options: Options) -> Result<Self> {
+            let mut config = options.config.clone();
+            config.async_support($async_support);
+            config.epoch_interruption(true);
+            let engine = wasmtime::Engine::new(&config).context("Failed to create engine")?;
+            let module = match &options.tpl_path {
+                Some(path) => load_module(&engine, path)?,
+                None => builtin_module(&engine, options.cache_dir.as_deref())?,
+            };
 
+            let mut linker = Linker::new(&engine);
+            p1::$linker_fn(&mut linker, |s: &mut LinkerState| &mut s.wasi)
+                .context("Failed to link WASI")?;
 
-    ) => {
-        fn get_runtime() -> &'static PrologRuntime {
-            $runtime.get_or_init(|| {
-                let mut config = wasmtime::Config::new();
-                config.async_support($async_support);
-                let engine = wasmtime::Engine::new(&config).expect("Failed to create async engine");
-                let module = if cfg!(precompiled_wasm) {
-                    unsafe { Module::deserialize(&engine, TPL_BYTES) }.unwrap_or_else(|e| {
-                        eprintln!("AOT load failed ({}), recompiling...", e);
-                        Module::new(&engine, TPL_BYTES).expect("Final fallback failed")
-                    })
-                } else {
-                    Module::new(&engine, TPL_BYTES).expect("Failed to compile raw WASM")
-                };
-
-                let mut linker = Linker::new(&engine);
-                p1::$linker_fn(&mut linker, |s: &mut LinkerState| &mut s.wasi)
-                    .expect("Failed to link WASI");
-
-                PrologRuntime {
-                    engine,
-                    module,
-                    linker,
-                }
+            let ticker = EpochTicker::spawn(&engine);
+            Ok(Self {
+                engine,
+                module,
+                linker,
+                options,
+                _ticker: ticker,
             })
         }
+
+        pub fn options(&self) -> &Options {
+            &self.options
+        }
     };
 }
 
@@ -70,6 +200,7 @@ macro_rules! run_prolog_impl {
             asyncness: $($async_kw:ident)?,
             instantiate_fn: $inst_fn:ident,
             call_fn: $call_fn:ident,
+            deadline_update: $update:ident,
             await_token: $($await:tt)*
         ) => {
             		/// Runs goal and specific input.
@@ -79,16 +210,42 @@ macro_rules! run_prolog_impl {
             		/// Input doesn't have to be a query - it can include modules etc.,
             		/// all of it is fed through --consult flat to WASM tpl binary through STDIN.
             		///
-                pub $($async_kw)? fn run_prolog(goal: &str, input: &str) -> Result<String> {
-                    // At this point runtime should be initialized
-                    let runtime = Self::get_runtime();
+                pub $($async_kw)? fn run_prolog(&self, goal: &str, input: &str) -> Result<String> {
+                    self.run_prolog_with_token(goal, input, &CancellationToken::new())
+                        $($await)*
+                }
+
+            		/// Same as `run_prolog`, but can be interrupted with `token`.
+            		///
+            		/// Interrupted run returns [`Cancelled`] error.
+                pub $($async_kw)? fn run_prolog_with_token(
+                    &self,
+                    goal: &str,
+                    input: &str,
+                    token: &CancellationToken,
+                ) -> Result<String> {
+                    if token.is_cancelled() {
+                        return Err(Cancelled.into());
+                    }
 
-                    let (wasi, stdout, stderr) = build_wasi(goal, input)?;
-                    let mut store = Store::new(&runtime.engine, LinkerState { wasi });
+                    let (wasi, stdout, stderr) = build_wasi(goal, input, &self.options)?;
+                    let limits = build_limits(&self.options);
+                    let mut store = Store::new(&self.engine, LinkerState { wasi, limits });
+                    store.limiter(|s| &mut s.limits);
 
-                    let instance = runtime
+                    let callback_token = token.clone();
+                    store.set_epoch_deadline(1);
+                    store.epoch_deadline_callback(move |_| {
+                        if callback_token.is_cancelled() {
+                            Ok(UpdateDeadline::Interrupt)
+                        } else {
+                            Ok(UpdateDeadline::$update(1))
+                        }
+                    });
+
+                    let instance = self
                         .linker
-                        .$inst_fn(&mut store, &runtime.module)
+                        .$inst_fn(&mut store, &self.module)
                         $($await)*
                         ?;
 
@@ -98,76 +255,288 @@ macro_rules! run_prolog_impl {
                         .$call_fn(&mut store, ())
                         $($await)* ;
 
+                    if token.is_cancelled() {
+                        return Err(Cancelled.into());
+                    }
+
                     process_output(stdout, stderr)
                 }
             }
 }
-#[cfg(feature = "async")]
-pub struct EngineAsync;
-#[cfg(feature = "sync")]
-pub struct Engine;
+
+/// Static API running on a process-wide runtime created with default
+/// [`Options`] on first use.
+macro_rules! default_engine_impl {
+    (
+        runtime: $runtime:ident,
+        runtime_type: $runtime_type:ident,
+        asyncness: $($async_kw:ident)?,
+        await_token: $($await:tt)*
+    ) => {
+        fn get_runtime() -> &'static $runtime_type {
+            $runtime.get_or_init(|| {
+                $runtime_type::new(Options::default()).expect("Failed to create Prolog runtime")
+            })
+        }
+
+		/// Initialize engine
+        pub fn init() {
+            Self::get_runtime();
+        }
+
+        /// Runs goal on the default runtime.
+        pub $($async_kw)? fn run_prolog(goal: &str, input: &str) -> Result<String> {
+            Self::get_runtime().run_prolog(goal, input) $($await)*
+        }
+
+        /// Runs goal on the default runtime, can be interrupted with `token`.
+        pub $($async_kw)? fn run_prolog_with_token(
+            goal: &str,
+            input: &str,
+            token: &CancellationToken,
+        ) -> Result<String> {
+            Self::get_runtime().run_prolog_with_token(goal, input, token) $($await)*
+        }
+    };
+}
 
 #[cfg(feature = "sync")]
-impl Engine {
-    get_runtime_impl!(
-        runtime: RUNTIME_SYNC,
+impl Runtime {
+    runtime_new_impl!(
         async_support: false,
         linker_fn: add_to_linker_sync
     );
 
-		/// Initialize engine
-    pub fn init() {
-        Self::get_runtime();
-    }
-
     run_prolog_impl!(
         asyncness: ,
         instantiate_fn: instantiate,
         call_fn: call,
+        deadline_update: Continue,
         await_token:
     );
 }
 
 #[cfg(feature = "async")]
-impl EngineAsync {
-    get_runtime_impl!(
-        runtime: RUNTIME_ASYNC,
+impl RuntimeAsync {
+    runtime_new_impl!(
         async_support: true,
         linker_fn: add_to_linker_async
     );
-		/// Initialize engine
-    pub fn init() {
-        Self::get_runtime();
-    }
+
     run_prolog_impl!(
         asyncness: async,
         instantiate_fn: instantiate_async,
         call_fn: call_async,
+        deadline_update: Yield,
         await_token: .await
     );
 }
 
-fn build_wasi(goal: &str, input: &str) -> Result<WasiCtxWithCtx> {
+#[cfg(feature = "async")]
+pub struct EngineAsync;
+#[cfg(feature = "sync")]
+pub struct Engine;
+
+#[cfg(feature = "sync")]
+impl Engine {
+    default_engine_impl!(
+        runtime: RUNTIME_SYNC,
+        runtime_type: Runtime,
+        asyncness: ,
+        await_token:
+    );
+}
+
+#[cfg(feature = "async")]
+impl EngineAsync {
+    default_engine_impl!(
+        runtime: RUNTIME_ASYNC,
+        runtime_type: RuntimeAsync,
+        asyncness: async,
+        await_token: .await
+    );
+}
+
+/// Precompiled bundled build, without `raw-wasm` there's nothing to fall
+/// back to.
+#[cfg(all(precompiled_wasm, not(feature = "raw-wasm")))]
+fn builtin_module(engine: &wasmtime::Engine, _cache_dir: Option<&Path>) -> Result<Module> {
+    unsafe { Module::deserialize(engine, TPL_BYTES) }.context(
+        "Bundled Trealla build doesn't fit engine config, enable `raw-wasm` feature or set \
+         `tpl_path`",
+    )
+}
+
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm"))]
+fn builtin_module(engine: &wasmtime::Engine, cache_dir: Option<&Path>) -> Result<Module> {
+    #[cfg(precompiled_wasm)]
+    match unsafe { Module::deserialize(engine, TPL_BYTES) } {
+        Ok(module) => return Ok(module),
+        Err(e) => eprintln!("AOT load failed ({}), recompiling...", e),
+    }
+
+    match cache_dir {
+        Some(cache_dir) => cached_module(engine, TPL_WASM, cache_dir),
+        None => Module::new(engine, TPL_WASM).context("Failed to compile raw WASM"),
+    }
+}
+
+/// Compiles `wasm` once per wasmtime version, engine settings and CPU
+/// features, later launches only deserialize the artifact from `cache_dir`.
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm", test))]
+fn cached_module(engine: &wasmtime::Engine, wasm: &[u8], cache_dir: &Path) -> Result<Module> {
+    let artifact = artifact_path(engine, wasm, cache_dir);
+    if artifact.exists() {
+        // Safety: artifacts in cache dir are only written by `store_artifact`
+        match unsafe { Module::deserialize_file(engine, &artifact) } {
+            Ok(module) => return Ok(module),
+            Err(e) => eprintln!("Cached {} rejected ({}), recompiling...", artifact.display(), e),
+        }
+    }
+
+    let module = Module::new(engine, wasm).context("Failed to compile raw WASM")?;
+    if let Err(e) = store_artifact(&module, &artifact) {
+        eprintln!("Can't cache {} ({})", artifact.display(), e);
+    }
+    Ok(module)
+}
+
+/// Artifact name stays the same across launches and Rust releases, unlike
+/// `DefaultHasher` output.
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm", test))]
+fn artifact_path(engine: &wasmtime::Engine, wasm: &[u8], cache_dir: &Path) -> PathBuf {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = Fnv1a::default();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    hasher.write(wasm);
+    cache_dir.join(format!("tpl-{:016x}.cwasm", hasher.finish()))
+}
+
+/// 64-bit FNV-1a, fixed by its spec.
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm", test))]
+struct Fnv1a(u64);
+
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm", test))]
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+#[cfg(any(not(precompiled_wasm), feature = "raw-wasm", test))]
+impl std::hash::Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Loads Trealla build from disk, reusing compiled artifact when it's
+/// newer than the source and was compiled with compatible settings.
+fn load_module(engine: &wasmtime::Engine, path: &Path) -> Result<Module> {
+    let artifact = path.with_extension("cwasm");
+    if is_newer(&artifact, path) {
+        match unsafe { Module::deserialize_file(engine, &artifact) } {
+            Ok(module) => {
+                check_module(&module)
+                    .with_context(|| format!("Incompatible Trealla build {}", path.display()))?;
+                return Ok(module);
+            },
+            Err(e) => eprintln!("Cached {} rejected ({}), recompiling...", artifact.display(), e),
+        }
+    }
+
+    let module = Module::from_file(engine, path)
+        .with_context(|| format!("Failed to compile {}", path.display()))?;
+    check_module(&module)
+        .with_context(|| format!("Incompatible Trealla build {}", path.display()))?;
+
+    if let Err(e) = store_artifact(&module, &artifact) {
+        eprintln!("Can't cache {} ({})", artifact.display(), e);
+    }
+    Ok(module)
+}
+
+/// Writes serialized module through a temporary file, so concurrent
+/// launches never see half-written artifact.
+fn store_artifact(module: &Module, artifact: &Path) -> Result<()> {
+    if let Some(dir) = artifact.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = artifact.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&tmp, module.serialize()?)?;
+    std::fs::rename(&tmp, artifact)?;
+    Ok(())
+}
+
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(path), modified(than)) {
+        (Some(a), Some(b)) => a >= b,
+        _ => false,
+    }
+}
+
+/// Checks that module looks like a Trealla WASI build we know how to run.
+fn check_module(module: &Module) -> Result<()> {
+    let start = module.get_export("_start");
+    if start.is_none_or(|ty| ty.func().is_none()) {
+        bail!("missing `_start` function export");
+    }
+    let memory = module.get_export("memory");
+    if memory.is_none_or(|ty| ty.memory().is_none()) {
+        bail!("missing `memory` export");
+    }
+    if let Some(import) = module.imports().find(|i| i.module() != WASI_MODULE) {
+        bail!(
+            "unsupported import `{}::{}`, only {} is provided",
+            import.module(),
+            import.name(),
+            WASI_MODULE
+        );
+    }
+    Ok(())
+}
+
+fn build_wasi(goal: &str, input: &str, options: &Options) -> Result<WasiCtxWithCtx> {
     let mut sb = String::new();
     writeln!(sb, "{}", input)?;
     let goal = format!("{}, halt", goal);
 
     let stdin = MemoryInputPipe::new(sb);
-    let stdout = MemoryOutputPipe::new(65535);
-    let stderr = MemoryOutputPipe::new(65535);
+    let stdout = MemoryOutputPipe::new(options.output_capacity);
+    let stderr = MemoryOutputPipe::new(options.output_capacity);
 
-    let ctx = WasiCtxBuilder::new()
+    let mut builder = WasiCtxBuilder::new();
+    builder
         .stdin(stdin)
         .stdout(stdout.clone())
         .stderr(stdout.clone())
         .args(&["tpl", "-q", "--consult", "-g", &goal])
-        .preopened_dir(".", "/", DirPerms::READ, FilePerms::READ)
-        .expect("Can't open current dir as root")
-        .env("PWD", "/")
-        .build_p1();
+        .env("PWD", "/");
+    for (host, guest) in &options.preopens {
+        builder
+            .preopened_dir(host, guest, DirPerms::READ, FilePerms::READ)
+            .with_context(|| format!("Can't open {} as {}", host.display(), guest))?;
+    }
+    let ctx = builder.build_p1();
     Ok((ctx, stdout, stderr))
 }
 
+fn build_limits(options: &Options) -> StoreLimits {
+    let mut limits = StoreLimitsBuilder::new();
+    if let Some(memory_limit) = options.memory_limit {
+        limits = limits.memory_size(memory_limit);
+    }
+    limits.build()
+}
+
 pub(crate) fn process_output(
     stdout: MemoryOutputPipe,
     stderr: MemoryOutputPipe,
@@ -191,3 +560,110 @@ pub(crate) fn process_output(
     Ok(output_str)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(wat: &str) -> Module {
+        Module::new(&wasmtime::Engine::default(), wat).unwrap()
+    }
+
+    fn rejection(wat: &str) -> String {
+        check_module(&module(wat)).unwrap_err().to_string()
+    }
+
+    /// Empty directory under the system temp dir, unique per test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let name = format!("trealla-wasm-{}-{}", std::process::id(), name);
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn exports(module: &Module) -> Vec<String> {
+        module.exports().map(|export| export.name().to_string()).collect()
+    }
+
+    const WASM: &str = r#"(module (func (export "cached")))"#;
+
+    #[test]
+    fn artifact_path_is_stable() {
+        let engine = wasmtime::Engine::default();
+        let dir = Path::new("cache");
+        let path = artifact_path(&engine, WASM.as_bytes(), dir);
+
+        assert_eq!(path, artifact_path(&engine, WASM.as_bytes(), dir));
+        assert_ne!(path, artifact_path(&engine, b"(module)", dir));
+    }
+
+    #[test]
+    fn cached_artifact_is_used() {
+        let engine = wasmtime::Engine::default();
+        let dir = scratch_dir("hit");
+        // Different module under the right name, it's only read back
+        let other = Module::new(&engine, r#"(module (func (export "other")))"#).unwrap();
+        store_artifact(&other, &artifact_path(&engine, WASM.as_bytes(), &dir)).unwrap();
+
+        let module = cached_module(&engine, WASM.as_bytes(), &dir).unwrap();
+        assert_eq!(exports(&module), vec!["other"]);
+    }
+
+    #[test]
+    fn stale_artifact_is_replaced() {
+        let engine = wasmtime::Engine::default();
+        let dir = scratch_dir("stale");
+        let artifact = artifact_path(&engine, WASM.as_bytes(), &dir);
+        std::fs::write(&artifact, b"not a module").unwrap();
+
+        let module = cached_module(&engine, WASM.as_bytes(), &dir).unwrap();
+        assert_eq!(exports(&module), vec!["cached"]);
+        let stored = unsafe { Module::deserialize_file(&engine, &artifact) }.unwrap();
+        assert_eq!(exports(&stored), vec!["cached"]);
+    }
+
+    #[test]
+    fn unwritable_cache_still_compiles() {
+        let engine = wasmtime::Engine::default();
+        let file = scratch_dir("unwritable").join("file");
+        std::fs::write(&file, b"").unwrap();
+        // A directory can't be created under a file, whoever runs the tests
+        let dir = file.join("cache");
+
+        let module = Module::new(&engine, WASM).unwrap();
+        let artifact = artifact_path(&engine, WASM.as_bytes(), &dir);
+        assert!(store_artifact(&module, &artifact).is_err());
+        let module = cached_module(&engine, WASM.as_bytes(), &dir).unwrap();
+        assert_eq!(exports(&module), vec!["cached"]);
+    }
+
+    #[test]
+    fn wasi_build_is_accepted() {
+        let wat = r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")))"#;
+        assert!(check_module(&module(wat)).is_ok());
+    }
+
+    #[test]
+    fn build_without_start_is_rejected() {
+        let wat = r#"(module (memory (export "memory") 1))"#;
+        assert!(rejection(wat).contains("_start"));
+    }
+
+    #[test]
+    fn build_without_memory_is_rejected() {
+        let wat = r#"(module (func (export "_start")))"#;
+        assert!(rejection(wat).contains("memory"));
+    }
+
+    #[test]
+    fn non_wasi_import_is_rejected() {
+        let wat = r#"(module
+            (import "env" "host_call" (func))
+            (memory (export "memory") 1)
+            (func (export "_start")))"#;
+        assert!(rejection(wat).contains("env::host_call"));
+    }
+}